@@ -18,10 +18,10 @@ impl <'a> FileSystem {
     pub fn create<P>(&'a mut self, path: P) -> MyResult<File<'a>> where P: AsRef<Path> {
         self.fs_impl.create(path).map(|f| File{ f_impl: f })
     }
-    pub fn bufread<P>(&'a mut self, path: P) -> MyResult<BufReader<File>> where P: AsRef<Path> {
+    pub fn bufread<P>(&'a mut self, path: P) -> MyResult<BufReader<File<'a>>> where P: AsRef<Path> {
         self.open(path).map(std::io::BufReader::new)
     }
-    pub fn bufwrite<P>(&'a mut self, path: P) -> MyResult<BufWriter<File>> where P: AsRef<Path> {
+    pub fn bufwrite<P>(&'a mut self, path: P) -> MyResult<BufWriter<File<'a>>> where P: AsRef<Path> {
         self.create(path).map(std::io::BufWriter::new)
     }
 }
@@ -56,7 +56,7 @@ enum FileSystemImpl {
 
 impl Default for FileSystemImpl {
     fn default() -> Self {
-        OSFileSystem::default().into()
+        OSFileSystem.into()
     }
 }
 
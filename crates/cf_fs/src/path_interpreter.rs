@@ -69,9 +69,7 @@ impl PathInterpreter {
     fn dedup<'a, 'b: 'a>(&self, input: Cow<'a, str>) -> MyResult<Cow<'b, str>> {
         self.sequenced_plugin
             .iter()
-            .fold(Ok(input), |inp, plugin| {
-                plugin.output(inp?)
-            })
+            .try_fold(input, |inp, plugin| plugin.output(inp))
             // canonicalize
             .map(|s| {
                 dedup_path_sep(s)
@@ -105,7 +103,7 @@ mod test {
 
     use super::dedup_path_sep;
 
-    fn dedup(input: &str) -> Cow<str> {
+    fn dedup(input: &str) -> Cow<'_, str> {
         dedup_path_sep(input.into())
     }
     #[test]
@@ -3,4 +3,7 @@ mod utils;
 mod wrapper;
 pub use error_handling::*;
 pub use utils::*;
+// the macros are `#[macro_export]`ed at the crate root, so this re-exports
+// nothing today; kept so that items added to `wrapper` stay public
+#[allow(unused_imports)]
 pub use wrapper::*;
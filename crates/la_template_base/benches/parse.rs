@@ -0,0 +1,50 @@
+//! Throughput of parsing and rendering large templates:
+//! `cargo bench -p la_template_base`
+
+use std::io::Cursor;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use la_template_base::*;
+use serde_json::json;
+
+/// About `size` bytes of YAML-ish text with a substitution every few lines
+fn template(size: usize) -> String {
+    let chunk = "- name: Launch ${name} instance\n  command: multipass launch --cpus 2 --mem 4G \\$HOME/${image}\n  register: launched\n  changed_when: true\n";
+    chunk.repeat(size / chunk.len() + 1)
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for size in [64 << 10, 16 << 20] {
+        let src = template(size);
+        group.throughput(Throughput::Bytes(src.len() as u64));
+        group.bench_function(format!("borrowed/{}KiB", size >> 10), |b| {
+            b.iter(|| parse_str(black_box(&src)).expect("Should parse"))
+        });
+        group.bench_function(format!("bufread/{}KiB", size >> 10), |b| {
+            b.iter(|| parse_template(Cursor::new(black_box(src.as_bytes()))).expect("Should parse"))
+        });
+    }
+    group.finish();
+}
+
+fn bench_render(c: &mut Criterion) {
+    let src = template(16 << 20);
+    let variables: VariableMap = json!({ "name": "worker", "image": "jammy.img" }).into();
+    let mut group = c.benchmark_group("render");
+    group.throughput(Throughput::Bytes(src.len() as u64));
+    let borrowed = parse_str(&src).expect("Should parse");
+    group.bench_function("borrowed/16MiB", |b| {
+        b.iter(|| borrowed.generate(black_box(&variables), Default::default()).expect("Should render"))
+    });
+    let owned: Template = parse_template(Cursor::new(src.as_bytes())).expect("Should parse").into();
+    group.bench_function("owned/16MiB", |b| {
+        b.iter(|| GenerateTemplate { template: &owned, variables: &variables, options: Default::default() }
+            .generate()
+            .expect("Should render"))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse, bench_render);
+criterion_main!(benches);
@@ -0,0 +1,149 @@
+//! Rendering one template against many variable sets
+
+use common::{res_err, res_ok, MyResult};
+use simple_error::simple_error;
+
+use crate::{
+    begin_render, escape_after, evaluate, substitute, validate_refs, BorrowedTemplate, BorrowedToken, EscapeMode,
+    Expression, Filter, FrontMatter, GenerateOptions, Template, TemplateTrait, Token, VariableMap,
+};
+
+/// A token of a [BatchGenerate] plan, with its symbol and escaping resolved
+enum Step<'t> {
+    Literal(&'t [u8]),
+    Value {
+        name: &'t str,
+        filters: &'t [Filter],
+        escape: Option<EscapeMode>,
+    },
+    Expr {
+        expression: &'t Expression,
+        escape: Option<EscapeMode>,
+    },
+}
+
+/// Renders a template against many variable sets. Symbols are resolved and
+/// escaping is decided once, when the plan is made, and reused for every set.
+pub struct BatchGenerate<'t> {
+    symbols: Vec<&'t str>,
+    options: GenerateOptions,
+    steps: Vec<Step<'t>>,
+    /// Length of the literals, to size the output
+    literal_len: usize,
+}
+
+/// What a plan is made from: an owned or a borrowed token
+enum PlanToken<'t> {
+    Literal(&'t [u8]),
+    Var(u8, &'t [Filter]),
+    Expr(&'t Expression),
+    Tag(&'t str),
+}
+
+impl<'t> BatchGenerate<'t> {
+    pub fn new(template: &'t Template, options: GenerateOptions) -> MyResult<Self> {
+        let symbols = template.symbols().iter().map(String::as_str).collect();
+        let tokens = template.tokens().iter().map(|tok| match tok {
+            Token::Str(s) => PlanToken::Literal(s.as_bytes()),
+            Token::Bytes(bytes) => PlanToken::Literal(bytes),
+            Token::Var(idx) => PlanToken::Var(*idx, &[]),
+            Token::Subst(subst) => PlanToken::Var(subst.var, &subst.filters),
+            Token::Expr(expression) => PlanToken::Expr(expression),
+            Token::Tag(tag) => PlanToken::Tag(&tag.name),
+        });
+        Self::plan(symbols, template.front_matter(), tokens, options)
+    }
+    /// Plans a [BorrowedTemplate], whose literals are not copied either
+    pub fn from_borrowed(template: &'t BorrowedTemplate<'_>, options: GenerateOptions) -> MyResult<Self> {
+        let symbols = template.symbols().to_vec();
+        let tokens = template.tokens().iter().map(|tok| match tok {
+            BorrowedToken::Str(s) => PlanToken::Literal(s.as_bytes()),
+            BorrowedToken::Bytes(bytes) => PlanToken::Literal(bytes),
+            BorrowedToken::Var(idx) => PlanToken::Var(*idx, &[]),
+            BorrowedToken::Subst(subst) => PlanToken::Var(subst.var, &subst.filters),
+            BorrowedToken::Expr(expression) => PlanToken::Expr(expression),
+            BorrowedToken::Tag(tag) => PlanToken::Tag(&tag.name),
+        });
+        Self::plan(symbols, template.front_matter(), tokens, options)
+    }
+    fn plan<I>(symbols: Vec<&'t str>, front_matter: &FrontMatter, tokens: I, options: GenerateOptions) -> MyResult<Self>
+    where
+        I: Iterator<Item = PlanToken<'t>>,
+    {
+        let symbol = |idx: u8| symbols
+            .get(idx as usize)
+            .copied()
+            .ok_or_else(|| simple_error!("Idx out of bounds: {}", idx));
+        let default_escape = front_matter.escape.or(options.escape);
+        let steps = tokens
+            .map(|tok| res_ok(match tok {
+                PlanToken::Literal(bytes) => Step::Literal(bytes),
+                PlanToken::Var(idx, filters) => Step::Value {
+                    name: symbol(idx)?,
+                    filters,
+                    escape: escape_after(filters, default_escape),
+                },
+                PlanToken::Expr(expression) => {
+                    // checks the symbols once, so that rendering may index them
+                    expression.expr.vars().into_iter().try_for_each(|var| symbol(var).map(drop))?;
+                    Step::Expr {
+                        expression,
+                        escape: escape_after(&expression.filters, default_escape),
+                    }
+                }
+                PlanToken::Tag(name) => return res_err(simple_error!("Unsupported block tag {:?}", name)),
+            }))
+            .collect::<MyResult<Vec<_>>>()?;
+        let literal_len = steps.iter()
+            .map(|step| match step {
+                Step::Literal(bytes) => bytes.len(),
+                Step::Value { .. } | Step::Expr { .. } => 0,
+            })
+            .sum();
+        res_ok(Self { symbols, options, steps, literal_len })
+    }
+    /// Renders one variable set, see [crate::GenerateTemplate::generate_bytes]
+    pub fn generate_bytes(&self, variables: &VariableMap) -> MyResult<Vec<u8>> {
+        let builtins = begin_render(variables, &self.options);
+        validate_refs(&self.symbols, variables, &self.options)?;
+        let mut out = Vec::with_capacity(self.literal_len);
+        for step in &self.steps {
+            match step {
+                Step::Literal(bytes) => out.extend_from_slice(bytes),
+                Step::Value { name, filters, escape } => {
+                    let value = substitute(variables, &self.options, &builtins, name, filters, *escape, &out)?;
+                    out.extend_from_slice(value.as_bytes());
+                }
+                Step::Expr { expression, escape } => {
+                    let value = evaluate(variables, &self.options, &builtins, &self.symbols, expression, *escape, &out)?;
+                    out.extend_from_slice(value.as_bytes());
+                }
+            }
+        }
+        res_ok(out)
+    }
+    /// Renders one variable set, see [crate::GenerateTemplate::generate]
+    pub fn generate(&self, variables: &VariableMap) -> MyResult<String> {
+        String::from_utf8(self.generate_bytes(variables)?)
+            .map_err(|_| simple_error!("Template holds binary literals, render it with generate_bytes").into())
+    }
+    /// Lazily renders every variable set of `records`, in order. A set that
+    /// fails yields its error without stopping the others.
+    pub fn generate_all<'b, I>(&'b self, records: I) -> impl Iterator<Item = MyResult<String>> + 'b
+    where
+        I: IntoIterator + 'b,
+        I::Item: Into<VariableMap>,
+    {
+        records.into_iter().map(move |variables| self.generate(&variables.into()))
+    }
+}
+
+/// Renders `template` against every variable set of `records`, see [BatchGenerate]
+pub fn generate_batch<'t, I>(template: &'t Template, records: I) -> MyResult<impl Iterator<Item = MyResult<String>> + 't>
+where
+    I: IntoIterator + 't,
+    I::Item: Into<VariableMap>,
+{
+    let batch = BatchGenerate::new(template, Default::default())?;
+    res_ok(records.into_iter().map(move |variables| batch.generate(&variables.into())))
+}
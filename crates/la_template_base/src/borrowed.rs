@@ -0,0 +1,622 @@
+//! Zero-copy parsing: literals borrow from the source, be it a `&str`, a
+//! file read into memory or a memory map, and delimiters are found with
+//! SIMD-accelerated searches.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use common::{res_err, res_ok, MyResult};
+use itertools::Itertools;
+use memchr::memmem;
+use serde::{Deserialize, Serialize};
+use simple_error::simple_error;
+
+use crate::{
+    is_var_name, parse_substitution, whitespace::{indent_at, layout_at, strip_trim_markers, trim_standalone}, BatchGenerate,
+    ConcreteTemplate, Escapes, Expr, Expression, Filter, FrontMatter, GenerateOptions, ParseOptions, Substitution, Tag,
+    Token, VariableMap, FRONT_MATTER_FENCE,
+};
+
+/// [Token] whose literals borrow from the parsed source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BorrowedToken<'a> {
+    Str(&'a str),
+    /// A literal that is not UTF-8, see [ParseOptions::binary]
+    Bytes(&'a [u8]),
+    Var(u8),
+    Subst(Substitution),
+    Expr(Expression),
+    Tag(Tag),
+}
+
+/// [ConcreteTemplate] whose literals and symbols borrow from the parsed source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BorrowedTemplate<'a> {
+    front_matter: FrontMatter,
+    tokens: Vec<BorrowedToken<'a>>,
+    symbols: Vec<&'a str>,
+}
+
+impl<'a> BorrowedTemplate<'a> {
+    pub fn tokens(&self) -> &[BorrowedToken<'a>] {
+        &self.tokens
+    }
+    pub fn symbols(&self) -> &[&'a str] {
+        &self.symbols
+    }
+    pub fn front_matter(&self) -> &FrontMatter {
+        &self.front_matter
+    }
+    /// Copies the literals out, merging adjacent ones
+    pub fn into_owned(self) -> ConcreteTemplate {
+        let mut tokens: Vec<Token> = Vec::with_capacity(self.tokens.len());
+        for tok in self.tokens {
+            match (tokens.last_mut(), tok) {
+                (Some(Token::Str(last)), BorrowedToken::Str(s)) => last.push_str(s),
+                (Some(Token::Bytes(last)), BorrowedToken::Str(s)) => last.extend_from_slice(s.as_bytes()),
+                (Some(Token::Bytes(last)), BorrowedToken::Bytes(b)) => last.extend_from_slice(b),
+                (Some(last), BorrowedToken::Bytes(b)) if matches!(last, Token::Str(_)) => {
+                    *last = Token::Bytes([last.literal_bytes(), b].concat());
+                }
+                (_, BorrowedToken::Str(s)) => tokens.push(Token::Str(s.to_string())),
+                (_, BorrowedToken::Bytes(b)) => tokens.push(Token::Bytes(b.to_vec())),
+                (_, BorrowedToken::Var(var)) => tokens.push(Token::Var(var)),
+                (_, BorrowedToken::Subst(subst)) => tokens.push(Token::Subst(subst)),
+                (_, BorrowedToken::Expr(expression)) => tokens.push(Token::Expr(expression)),
+                (_, BorrowedToken::Tag(tag)) => tokens.push(Token::Tag(tag)),
+            }
+        }
+        ConcreteTemplate {
+            front_matter: self.front_matter,
+            tokens,
+            symbols: self.symbols.into_iter().map(str::to_string).collect(),
+        }
+    }
+    /// Renders without copying the template, see [BatchGenerate]
+    pub fn generate(&self, variables: &VariableMap, options: GenerateOptions) -> MyResult<String> {
+        BatchGenerate::from_borrowed(self, options)?.generate(variables)
+    }
+}
+
+/// Where a construct starts in its source
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    /// 1-based
+    pub line: usize,
+    /// 1-based, in chars
+    pub column: usize,
+    pub offset: usize,
+}
+
+impl Span {
+    pub(crate) fn at(src: &[u8], offset: usize) -> Self {
+        let line_start = memchr::memrchr(b'\n', &src[..offset]).map_or(0, |nl| nl + 1);
+        Self {
+            line: memchr::memchr_iter(b'\n', &src[..line_start]).count() + 1,
+            column: String::from_utf8_lossy(&src[line_start..offset]).chars().count() + 1,
+            offset,
+        }
+    }
+}
+
+/// An error, located in the parsed source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The template, if known
+    pub path: Option<PathBuf>,
+    /// 1-based
+    pub line: usize,
+    /// 1-based, in chars
+    pub column: usize,
+    /// Byte offset of the faulty construct or sequence
+    pub offset: usize,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn new(src: &[u8], offset: usize, message: String) -> Self {
+        Self::at(Span::at(src, offset), message)
+    }
+    pub fn at(span: Span, message: String) -> Self {
+        let Span { line, column, offset } = span;
+        Self { path: None, line, column, offset, message }
+    }
+    /// The diagnostic, located in the template at `path`
+    pub fn in_file<P: AsRef<Path>>(self, path: P) -> Self {
+        Self { path: Some(path.as_ref().to_path_buf()), ..self }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(path) = &self.path {
+            write!(f, "{}:", path.display())?;
+        }
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// Parses `src` without copying its literals. Fails with every syntax
+/// error of the template, one per line.
+pub fn parse_borrowed(src: &[u8], options: ParseOptions) -> MyResult<BorrowedTemplate<'_>> {
+    let (template, diagnostics) = parse_borrowed_recovering(src, options)?;
+    if diagnostics.is_empty() {
+        res_ok(template)
+    } else {
+        res_err(simple_error!("{}", diagnostics.iter().join("\n")))
+    }
+}
+
+/// [parse_borrowed] that goes on after syntax errors, returning what could
+/// be parsed along with the errors. Only invalid options fail.
+///
+/// ```
+/// # use la_template_base::*;
+/// let (template, diagnostics) = parse_borrowed_recovering(b"${name\n${x | nope} ok ${y", Default::default()).unwrap();
+/// assert_eq!(diagnostics.iter().map(ToString::to_string).collect::<Vec<_>>(), [
+///     "1:1: Unterminated '${': missing '}'",
+///     "2:1: Unknown filter \"nope\"",
+///     "2:16: Unterminated '${': missing '}'",
+/// ]);
+/// assert_eq!(template.tokens(), [BorrowedToken::Str(" ok ")]);
+/// ```
+pub fn parse_borrowed_recovering(src: &[u8], options: ParseOptions) -> MyResult<(BorrowedTemplate<'_>, Vec<Diagnostic>)> {
+    options.validate()?;
+    res_ok(Parser::new(src, options).call())
+}
+
+/// [parse_borrowed] with the default delimiters
+pub fn parse_str(src: &str) -> MyResult<BorrowedTemplate<'_>> {
+    parse_borrowed(src.as_bytes(), Default::default())
+}
+
+struct Parser<'a> {
+    src: &'a [u8],
+    options: ParseOptions,
+    /// Encoded delimiters
+    sigil: Vec<u8>,
+    open: Vec<u8>,
+    close: Vec<u8>,
+    escape: Vec<u8>,
+    /// Set by a `-}` trim marker, consumed by the next literal
+    trim_next: bool,
+    /// Positions in `tokens` where a comment was dropped
+    comments: Vec<usize>,
+    /// [FrontMatter::indent]
+    indent_all: bool,
+    /// Set by a `raw` tag until its `endraw`
+    in_raw: bool,
+    tokens: Vec<BorrowedToken<'a>>,
+    symbols: Vec<&'a str>,
+    symbol_idx: HashMap<&'a str, u8>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+fn encode(c: char) -> Vec<u8> {
+    c.encode_utf8(&mut [0; 4]).as_bytes().to_vec()
+}
+
+/// Length of the string literal `src` starts with, up to its closing quote,
+/// if it closes on the same line
+fn quoted_len(src: &[u8]) -> Option<usize> {
+    let mut escaped = false;
+    for (idx, &b) in src.iter().enumerate().skip(1) {
+        match (escaped, b) {
+            (_, b'\n') => return None,
+            (false, b'\\') => escaped = true,
+            (false, b'"') => return Some(idx),
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+impl<'a> Parser<'a> {
+    fn new(src: &'a [u8], options: ParseOptions) -> Self {
+        Self {
+            src,
+            options,
+            sigil: encode(options.sigil),
+            open: encode(options.open),
+            close: encode(options.close),
+            escape: encode(options.escape),
+            trim_next: false,
+            comments: Vec::new(),
+            indent_all: false,
+            in_raw: false,
+            tokens: Vec::new(),
+            symbols: Vec::new(),
+            symbol_idx: HashMap::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+    fn call(mut self) -> (BorrowedTemplate<'a>, Vec<Diagnostic>) {
+        let (front_matter, mut pos) = self.front_matter();
+        self.indent_all = front_matter.indent;
+        let mut lit_start = pos;
+        loop {
+            let Some(at) = self.next_special(pos) else {
+                self.push_literal(lit_start, self.src.len());
+                break;
+            };
+            if self.src[at..].starts_with(&self.sigil) {
+                self.push_literal(lit_start, at);
+                let known = self.symbols.len();
+                pos = match self.after_sigil(at + self.sigil.len()) {
+                    Ok(next) => next,
+                    Err(e) => {
+                        // variables of the faulty construct are not used
+                        for name in self.symbols.drain(known..) {
+                            self.symbol_idx.remove(name);
+                        }
+                        self.recover(at, e.to_string())
+                    }
+                };
+                lit_start = pos;
+                continue;
+            }
+            if self.options.escapes == Escapes::Sigil {
+                // a run of escapes is halved before the sigil, which an odd
+                // one makes literal, and kept as is anywhere else
+                let mut end = at;
+                while self.src[end..].starts_with(&self.escape) {
+                    end += self.escape.len();
+                }
+                pos = end;
+                if self.src[end..].starts_with(&self.sigil) {
+                    let run = (end - at) / self.escape.len();
+                    self.push_literal(lit_start, at + run / 2 * self.escape.len());
+                    lit_start = end;
+                    if run % 2 == 1 {
+                        pos += self.sigil.len();
+                    }
+                }
+                continue;
+            }
+            pos = at + self.escape.len();
+            let rest = &self.src[pos..];
+            if let Some(len) = [&self.sigil, &self.escape].iter().find(|c| rest.starts_with(c)).map(|c| c.len()) {
+                // the escaped character itself starts the next literal
+                self.push_literal(lit_start, at);
+                lit_start = pos;
+                pos += len;
+            } else if self.options.escapes == Escapes::C {
+                let unescaped = match String::from_utf8_lossy(&rest[..rest.len().min(4)]).chars().next() {
+                    Some('n') => "\n",
+                    Some('t') => "\t",
+                    Some('r') => "\r",
+                    Some('0') => "\0",
+                    // kept as is
+                    Some(c) => {
+                        self.error_at(at, format!("Unknown escape sequence '{}{}'", self.options.escape, c));
+                        continue;
+                    }
+                    None => {
+                        self.error_at(at, format!("Escape '{}' at the end of the template", self.options.escape));
+                        continue;
+                    }
+                };
+                self.push_literal(lit_start, at);
+                self.tokens.push(BorrowedToken::Str(unescaped));
+                pos += 1;
+                lit_start = pos;
+            }
+        }
+        trim_standalone(&mut self.tokens, &self.comments);
+        // raw tags only matter for the lines they stand alone on
+        self.tokens.retain(|tok| !matches!(tok, BorrowedToken::Tag(tag) if tag.name == "raw" || tag.name == "endraw"));
+        (BorrowedTemplate { front_matter, tokens: self.tokens, symbols: self.symbols }, self.diagnostics)
+    }
+    fn error_at(&mut self, offset: usize, message: String) {
+        self.diagnostics.push(Diagnostic::new(self.src, offset, message));
+    }
+    /// Records the `error` of the construct whose sigil is at `at` and
+    /// returns where parsing resumes: after its closing delimiter, or at the
+    /// next construct if it runs into one before
+    fn recover(&mut self, at: usize, error: String) -> usize {
+        let ParseOptions { sigil, open, close, .. } = self.options;
+        let start = at + self.sigil.len();
+        if !self.src[start..].starts_with(&self.open) {
+            self.error_at(at, error);
+            return start;
+        }
+        let start = start + self.open.len();
+        let next = memmem::find(&self.src[start..], &[self.sigil.as_slice(), &self.open].concat())
+            .map(|found| start + found);
+        let closed = memmem::find(&self.src[start..], &self.close)
+            .map(|found| start + found + self.close.len());
+        let (resume, error) = match (next, closed) {
+            (Some(next), Some(closed)) if next < closed => {
+                (next, format!("Unterminated '{}{}': missing '{}'", sigil, open, close))
+            }
+            (_, Some(closed)) => (closed, error),
+            (next, None) => (next.unwrap_or(self.src.len()), error),
+        };
+        self.error_at(at, error);
+        // whatever trim marker it had is not to be trusted
+        self.trim_next = false;
+        resume
+    }
+    /// Reads the [FrontMatter] if the source opens with [FRONT_MATTER_FENCE],
+    /// returning it with the position the template starts at
+    fn front_matter(&mut self) -> (FrontMatter, usize) {
+        let mut lines = self.src.split_inclusive(|b| *b == b'\n');
+        let is_fence = |line: &[u8]| line.trim_ascii_end() == FRONT_MATTER_FENCE.as_bytes();
+        match lines.next() {
+            Some(first) if is_fence(first) => {
+                let mut end = first.len();
+                for line in lines {
+                    if is_fence(line) {
+                        let front_matter = std::str::from_utf8(&self.src[first.len()..end])
+                            .map_err(|e| simple_error!("Invalid UTF-8 in front matter: {}", e).into())
+                            .and_then(FrontMatter::parse)
+                            .unwrap_or_else(|e| {
+                                self.error_at(first.len(), e.to_string());
+                                Default::default()
+                            });
+                        return (front_matter, end + line.len());
+                    }
+                    end += line.len();
+                }
+                self.error_at(0, format!("Unterminated front matter: missing closing {}", FRONT_MATTER_FENCE));
+                (Default::default(), first.len())
+            }
+            _ => (Default::default(), 0),
+        }
+    }
+    /// Where the next sigil, or escape character unless [Escapes::Off], is
+    /// at or after `pos`
+    fn next_special(&self, mut pos: usize) -> Option<usize> {
+        if self.options.escapes == Escapes::Off {
+            return memmem::find(&self.src[pos..], &self.sigil).map(|found| pos + found);
+        }
+        // the first bytes are only candidates with multi-byte delimiters
+        while let Some(found) = memchr::memchr2(self.sigil[0], self.escape[0], &self.src[pos..]) {
+            let at = pos + found;
+            if self.src[at..].starts_with(&self.sigil) || self.src[at..].starts_with(&self.escape) {
+                return Some(at);
+            }
+            pos = at + 1;
+        }
+        None
+    }
+    /// Pushes `src[start..end]` unless it is empty, honouring a pending `-}`.
+    /// Without [ParseOptions::binary], each invalid UTF-8 sequence is
+    /// reported and left out.
+    fn push_literal(&mut self, start: usize, end: usize) {
+        let src = self.src;
+        let bytes = &src[start..end];
+        let mut trim = std::mem::take(&mut self.trim_next);
+        if self.options.binary && std::str::from_utf8(bytes).is_err() {
+            // only ASCII whitespace can be told apart from binary data
+            let bytes = if trim { bytes.trim_ascii_start() } else { bytes };
+            if !bytes.is_empty() {
+                self.tokens.push(BorrowedToken::Bytes(bytes));
+            }
+            return;
+        }
+        let mut offset = start;
+        for chunk in bytes.utf8_chunks() {
+            let s = match std::mem::take(&mut trim) {
+                true => chunk.valid().trim_start(),
+                false => chunk.valid(),
+            };
+            if !s.is_empty() {
+                self.tokens.push(BorrowedToken::Str(s));
+            }
+            offset += chunk.valid().len();
+            if !chunk.invalid().is_empty() {
+                self.error_at(offset, format!("Invalid UTF-8 at byte offset {}", offset));
+                offset += chunk.invalid().len();
+            }
+        }
+    }
+    /// Applies a `${-` trim marker to the literal right before it
+    fn trim_last_literal(&mut self) {
+        let emptied = match self.tokens.last_mut() {
+            Some(BorrowedToken::Str(s)) => {
+                *s = s.trim_end();
+                s.is_empty()
+            }
+            Some(BorrowedToken::Bytes(b)) => {
+                *b = b.trim_ascii_end();
+                b.is_empty()
+            }
+            _ => false,
+        };
+        if emptied {
+            self.tokens.pop();
+            let len = self.tokens.len();
+            self.comments.iter_mut().for_each(|pos| *pos = (*pos).min(len));
+        }
+    }
+    /// Parses what follows the sigil at `pos`, returning where the next literal starts.
+    /// Comments are dropped, hence yield no token.
+    fn after_sigil(&mut self, pos: usize) -> MyResult<usize> {
+        let rest = &self.src[pos..];
+        if rest.starts_with(&self.open) {
+            let pos = pos + self.open.len();
+            if self.src[pos..].starts_with(b"#") {
+                return self.block_comment(pos + 1);
+            }
+            return self.braced_token(pos);
+        }
+        if rest.starts_with(b"#") {
+            // `$#` in the middle of a line is more likely shell than a comment
+            let line_start = memchr::memrchr(b'\n', &self.src[..pos]).map_or(0, |nl| nl + 1);
+            let indent = &self.src[line_start..pos - self.sigil.len()];
+            if !indent.iter().all(|b| matches!(b, b' ' | b'\t')) {
+                return res_err(simple_error!(
+                    "'{sigil}#' comments must start their line, write '{escape}{sigil}#' for the text or '{sigil}{open}# ... #{close}' for a comment",
+                    sigil = self.options.sigil,
+                    escape = self.options.escape,
+                    open = self.options.open,
+                    close = self.options.close,
+                ));
+            }
+            return res_ok(self.line_comment(pos + 1));
+        }
+        res_err(simple_error!("Expected '{}' or '#' after '{}'", self.options.open, self.options.sigil))
+    }
+    /// Skips `$# ...`, which starts its line, up to, but excluding, the end of the line
+    fn line_comment(&mut self, pos: usize) -> usize {
+        self.comments.push(self.tokens.len());
+        match memchr::memchr(b'\n', &self.src[pos..]) {
+            // the line ending goes on to start the next literal
+            Some(nl) if nl > 0 && self.src[pos + nl - 1] == b'\r' => pos + nl - 1,
+            Some(nl) => pos + nl,
+            None => self.src.len(),
+        }
+    }
+    /// Skips `${# ... #}`, honouring trim markers
+    fn block_comment(&mut self, pos: usize) -> MyResult<usize> {
+        let closing = [b"#".as_slice(), &self.close].concat();
+        let end = memmem::find(&self.src[pos..], &closing)
+            .map(|found| pos + found)
+            .ok_or_else(|| simple_error!(
+                "Unterminated '{}{}#': missing '#{}'",
+                self.options.sigil,
+                self.options.open,
+                self.options.close
+            ))?;
+        let body = &self.src[pos..end];
+        if body.starts_with(b"-") {
+            self.trim_last_literal();
+        }
+        self.trim_next = body.ends_with(b"-");
+        self.comments.push(self.tokens.len());
+        res_ok(end + closing.len())
+    }
+    /// Reads `{...}` right after `${`: either a `%` block tag or a substitution
+    fn braced_token(&mut self, pos: usize) -> MyResult<usize> {
+        let ParseOptions { sigil, open, close, .. } = self.options;
+        let end = self.find_close(pos)
+            .ok_or_else(|| simple_error!("Unterminated '{}{}': missing '{}'", sigil, open, close))?;
+        let content = std::str::from_utf8(&self.src[pos..end]).map_err(|e| simple_error!(
+            "Invalid UTF-8 in '{}{}...{}' at byte offset {}: {}",
+            sigil,
+            open,
+            close,
+            pos + e.valid_up_to(),
+            e
+        ))?;
+        let (is_tag, inner) = match content.strip_prefix('%') {
+            Some(tag) => (true, tag.strip_suffix('%')
+                .ok_or_else(|| simple_error!("Block tag {:?} is missing its closing '%{}'", content, close))?),
+            None => (false, content),
+        };
+        let (trim_before, inner, trim_after) = strip_trim_markers(inner);
+        if trim_before {
+            self.trim_last_literal();
+        }
+        self.trim_next = trim_after;
+        let sigil_at = pos - open.len_utf8() - sigil.len_utf8();
+        let token = if is_tag {
+            let mut tag = Tag::parse(inner)?;
+            tag.span = Some(Span::at(self.src, sigil_at));
+            match tag.name.as_str() {
+                "include" | "call" => tag.indent = indent_at(self.src, sigil_at),
+                "raw" => {
+                    // the content up to `endraw` is a literal, whatever it holds
+                    let content_start = end + self.close.len();
+                    let endraw_at = self.find_endraw(content_start)?;
+                    self.tokens.push(BorrowedToken::Tag(tag));
+                    self.push_literal(content_start, endraw_at);
+                    self.in_raw = true;
+                    return res_ok(endraw_at);
+                }
+                "endraw" if !std::mem::take(&mut self.in_raw) => {
+                    return res_err(simple_error!("endraw without a raw block"));
+                }
+                _ => {}
+            }
+            BorrowedToken::Tag(tag)
+        } else {
+            let (subject, mut filters) = parse_substitution(inner)?;
+            if self.indent_all && !filters.iter().any(Filter::indents) {
+                filters.push(Filter::Indent(String::new()));
+            }
+            for filter in &mut filters {
+                match filter {
+                    Filter::ToYaml(layout) | Filter::ToJson(layout) => *layout = layout_at(self.src, sigil_at),
+                    _ => {}
+                }
+            }
+            if !is_var_name(subject) {
+                let expr = Expr::parse(subject, &mut |name| self.symbol_idx(name))?;
+                BorrowedToken::Expr(Expression { expr, filters })
+            } else if filters.is_empty() {
+                BorrowedToken::Var(self.symbol_idx(subject)?)
+            } else {
+                BorrowedToken::Subst(Substitution { var: self.symbol_idx(subject)?, filters })
+            }
+        };
+        self.tokens.push(token);
+        res_ok(end + self.close.len())
+    }
+    /// Where the close delimiter of the construct whose content starts at
+    /// `pos` is, skipping string literals such as `${a + "}"}` and a format
+    /// spec filled with the delimiter, such as `${x:}>5}`
+    fn find_close(&self, pos: usize) -> Option<usize> {
+        let mut in_spec = self.src[pos..].starts_with(b"%");
+        let mut from = pos;
+        loop {
+            let found = from + memchr::memchr3(b'"', b':', self.close[0], &self.src[from..])?;
+            if self.src[found..].starts_with(&self.close) {
+                return Some(found);
+            }
+            from = match self.src[found] {
+                // an unterminated literal is left to the substitution parser to report
+                b'"' => quoted_len(&self.src[found..]).map_or(found + 1, |len| found + len + 1),
+                b':' if !std::mem::replace(&mut in_spec, true) => {
+                    let spec = &self.src[found + 1..];
+                    match spec.starts_with(&self.close) && matches!(spec.get(self.close.len()), Some(b'<' | b'>' | b'^')) {
+                        true => found + 1 + self.close.len(),
+                        false => found + 1,
+                    }
+                }
+                _ => found + 1,
+            };
+        }
+    }
+    /// Where the `endraw` tag closing a raw block that starts at `pos` begins
+    fn find_endraw(&self, pos: usize) -> MyResult<usize> {
+        let opening = [self.sigil.as_slice(), &self.open, b"%"].concat();
+        let closing = [b"%".as_slice(), &self.close].concat();
+        let mut from = pos;
+        while let Some(found) = memmem::find(&self.src[from..], &opening) {
+            let start = from + found;
+            from = start + opening.len();
+            let Some(len) = memmem::find(&self.src[from..], &closing) else {
+                break;
+            };
+            let inner = &self.src[from..from + len];
+            let inner = inner.strip_prefix(b"-").unwrap_or(inner);
+            let inner = inner.strip_suffix(b"-").unwrap_or(inner);
+            if inner.trim_ascii() == b"endraw" {
+                return res_ok(start);
+            }
+        }
+        let ParseOptions { sigil, open, close, .. } = self.options;
+        res_err(simple_error!("Unterminated raw block: missing {}{}% endraw %{}", sigil, open, close))
+    }
+    /// Index of `name` in the symbol table, registering it on first sight
+    fn symbol_idx(&mut self, name: &'a str) -> MyResult<u8> {
+        if !is_var_name(name) {
+            return res_err(simple_error!("Invalid variable name {:?}", name));
+        }
+        if let Some(idx) = self.symbol_idx.get(name) {
+            return res_ok(*idx);
+        }
+        let idx = u8::try_from(self.symbols.len())
+            .map_err(|_| simple_error!("Templates may only declare up to {} variables", u8::MAX as usize + 1))?;
+        self.symbols.push(name);
+        self.symbol_idx.insert(name, idx);
+        res_ok(idx)
+    }
+}
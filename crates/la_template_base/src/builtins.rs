@@ -0,0 +1,246 @@
+//! Variables of the reserved `la.` namespace, provided by the renderer rather
+//! than the variable map: `${la.now}`, `${la.uuid}`, `${la.template_path}`, ...
+
+use std::{
+    cell::OnceCell,
+    collections::hash_map::RandomState,
+    fmt,
+    hash::{BuildHasher, Hasher},
+    path::Path,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use common::{res_err, res_ok, MyResult};
+use simple_error::simple_error;
+
+/// Prefix of the names [Builtins] reserves
+pub const BUILTIN_PREFIX: &str = "la.";
+
+/// Every name [Builtins] knows, without [BUILTIN_PREFIX]
+pub const BUILTIN_NAMES: [&str; 9] = [
+    "now", "date", "timestamp", "uuid", "hostname", "user", "template_path", "template_name", "target",
+];
+
+type Clock = Arc<dyn Fn() -> SystemTime + Send + Sync>;
+type Rng = Arc<dyn Fn() -> u128 + Send + Sync>;
+
+/// Source of the `la.*` variables. The clock, the RNG and the host info default
+/// to the system's and can be pinned for tests and reproducible builds. The
+/// time and the UUID are taken once per render, see [RenderBuiltins], so
+/// every `${la.now}` of an output agrees:
+///
+/// ```
+/// # use la_template_base::Builtins;
+/// # use std::time::{Duration, UNIX_EPOCH};
+/// let builtins = Builtins::default()
+///     .with_clock(|| UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+///     .with_hostname("build-01");
+/// assert_eq!(builtins.get("la.now").unwrap(), "2023-11-14T22:13:20Z");
+/// ```
+#[derive(Clone)]
+pub struct Builtins {
+    clock: Clock,
+    rng: Rng,
+    hostname: Option<Arc<str>>,
+    user: Option<Arc<str>>,
+    template_path: Option<Arc<Path>>,
+    target: Option<Arc<Path>>,
+}
+
+/// The `la.*` values of one render, made by [Builtins::begin_render]. The
+/// time and the UUID are taken on first use and kept for the rest of it.
+pub struct RenderBuiltins<'b> {
+    builtins: &'b Builtins,
+    now: OnceCell<SystemTime>,
+    uuid: OnceCell<u128>,
+}
+
+impl Default for Builtins {
+    fn default() -> Self {
+        Self {
+            clock: Arc::new(SystemTime::now),
+            rng: Arc::new(random_u128),
+            hostname: None,
+            user: None,
+            template_path: None,
+            target: None,
+        }
+    }
+}
+
+/// Whether `name` belongs to the reserved `la.` namespace
+pub fn is_builtin(name: &str) -> bool {
+    name.starts_with(BUILTIN_PREFIX)
+}
+
+impl Builtins {
+    /// Time behind `la.now`, `la.date` and `la.timestamp`
+    pub fn with_clock<F: Fn() -> SystemTime + Send + Sync + 'static>(mut self, clock: F) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+    /// Random bits behind `la.uuid`
+    pub fn with_rng<F: Fn() -> u128 + Send + Sync + 'static>(mut self, rng: F) -> Self {
+        self.rng = Arc::new(rng);
+        self
+    }
+    /// `la.hostname`, read from the environment otherwise
+    pub fn with_hostname<S: AsRef<str>>(mut self, hostname: S) -> Self {
+        self.hostname = Some(hostname.as_ref().into());
+        self
+    }
+    /// `la.user`, read from the environment otherwise
+    pub fn with_user<S: AsRef<str>>(mut self, user: S) -> Self {
+        self.user = Some(user.as_ref().into());
+        self
+    }
+    /// `la.template_path` and `la.template_name`
+    pub fn with_template_path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.template_path = Some(path.as_ref().into());
+        self
+    }
+    /// `la.target`, the path being rendered to
+    pub fn with_target<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.target = Some(path.as_ref().into());
+        self
+    }
+    /// Whether `name` has a value in this render. Paths are only known
+    /// when the renderer was given them.
+    pub fn defines(&self, name: &str) -> bool {
+        match name.strip_prefix(BUILTIN_PREFIX) {
+            Some("template_path" | "template_name") => self.template_path.is_some(),
+            Some("target") => self.target.is_some(),
+            Some(name) => BUILTIN_NAMES.contains(&name),
+            None => false,
+        }
+    }
+    /// Value of the built-in `name`, e.g. `la.now`, in a render of its own
+    pub fn get(&self, name: &str) -> MyResult<String> {
+        self.begin_render().get(name)
+    }
+    /// Starts a render, which takes `la.now` and `la.uuid` afresh on their
+    /// first use
+    pub fn begin_render(&self) -> RenderBuiltins<'_> {
+        RenderBuiltins { builtins: self, now: OnceCell::new(), uuid: OnceCell::new() }
+    }
+}
+
+impl RenderBuiltins<'_> {
+    /// Value of the built-in `name` in this render, see [Builtins::get]
+    pub fn get(&self, name: &str) -> MyResult<String> {
+        let builtins = self.builtins;
+        match name.strip_prefix(BUILTIN_PREFIX) {
+            Some("now") => res_ok(rfc3339(self.unix_time())),
+            Some("date") => res_ok(rfc3339(self.unix_time())[..10].to_string()),
+            Some("timestamp") => res_ok(self.unix_time().to_string()),
+            Some("uuid") => res_ok(uuid_v4(*self.uuid.get_or_init(|| (builtins.rng)()))),
+            Some("hostname") => match &builtins.hostname {
+                Some(hostname) => res_ok(hostname.to_string()),
+                None => detect_hostname(),
+            },
+            Some("user") => match &builtins.user {
+                Some(user) => res_ok(user.to_string()),
+                None => env_var(&["USER", "USERNAME", "LOGNAME"])
+                    .ok_or_else(|| simple_error!("Cannot tell the current user for la.user").into()),
+            },
+            Some("template_path") => known_path(name, &builtins.template_path).map(|path| path.to_string_lossy().into_owned()),
+            Some("template_name") => known_path(name, &builtins.template_path).map(|path| {
+                path.file_name().unwrap_or(path.as_os_str()).to_string_lossy().into_owned()
+            }),
+            Some("target") => known_path(name, &builtins.target).map(|path| path.to_string_lossy().into_owned()),
+            _ => res_err(simple_error!(
+                "Unknown built-in variable {}, expected one of {}{}",
+                name,
+                BUILTIN_PREFIX,
+                BUILTIN_NAMES.join(&format!(", {}", BUILTIN_PREFIX))
+            )),
+        }
+    }
+    /// Seconds since the Unix epoch, negative before it
+    fn unix_time(&self) -> i64 {
+        let now = *self.now.get_or_init(|| (self.builtins.clock)());
+        match now.duration_since(UNIX_EPOCH) {
+            Ok(after) => after.as_secs() as i64,
+            Err(before) => -(before.duration().as_secs_f64().ceil() as i64),
+        }
+    }
+}
+
+/// Pinned values and whether the clock and RNG are shared
+impl fmt::Debug for Builtins {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Builtins")
+            .field("hostname", &self.hostname)
+            .field("user", &self.user)
+            .field("template_path", &self.template_path)
+            .field("target", &self.target)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Equal when they share their clock and RNG and pin the same values
+impl PartialEq for Builtins {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.clock, &other.clock)
+            && Arc::ptr_eq(&self.rng, &other.rng)
+            && self.hostname == other.hostname
+            && self.user == other.user
+            && self.template_path == other.template_path
+            && self.target == other.target
+    }
+}
+
+impl Eq for Builtins {}
+
+/// `YYYY-MM-DDThh:mm:ssZ` for `secs` since the Unix epoch
+fn rfc3339(secs: i64) -> String {
+    let (days, secs) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+    // civil_from_days, http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, secs / 3600, secs / 60 % 60, secs % 60
+    )
+}
+
+/// Random (version 4) UUID made of `bits`
+fn uuid_v4(bits: u128) -> String {
+    let bits = (bits & !(0xf << 76) & !(0x3 << 62)) | (0x4 << 76) | (0x2 << 62);
+    let hex = format!("{:032x}", bits);
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+/// Bits from the std hasher's random keys: unique enough for identifiers,
+/// not fit for secrets
+fn random_u128() -> u128 {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+    (0..2).fold(0, |bits, _| {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(nanos);
+        (bits << 64) | u128::from(hasher.finish())
+    })
+}
+
+fn known_path<'p>(name: &str, path: &'p Option<Arc<Path>>) -> MyResult<&'p Path> {
+    path.as_deref()
+        .ok_or_else(|| simple_error!("{} is not known to this render", name).into())
+}
+
+fn env_var(names: &[&str]) -> Option<String> {
+    names.iter().find_map(|name| std::env::var(name).ok().filter(|v| !v.is_empty()))
+}
+
+fn detect_hostname() -> MyResult<String> {
+    env_var(&["HOSTNAME", "COMPUTERNAME"])
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok().map(|h| h.trim().to_string()).filter(|h| !h.is_empty()))
+        .ok_or_else(|| simple_error!("Cannot tell the host name for la.hostname").into())
+}
@@ -0,0 +1,712 @@
+//! C-preprocessor-style directive mode, an alternative to `${...}` templates:
+//!
+//! ```text
+//! #ifndef COMMAND
+//! #error COMMAND expected to be defined
+//! #endif
+//! #define STR(x) #x
+//! - name: Generate ssh key
+//!   COMMAND: path STR(~/.ssh/id_rsa)
+//! ```
+//!
+//! Supported directives are `#define` (object-like, function-like and
+//! variadic macros with `#` and `##`), `#undef`, `#include`, `#if`, `#ifdef`,
+//! `#ifndef`, `#elif`, `#else`, `#endif`, `#error` and `#warning`.
+//! `#pragma` and `#line` are accepted and ignored, as are lines whose `#`
+//! is not followed by a directive name, e.g. `#// author comment`.
+//!
+//! Unlike `cpp`, no line markers are emitted, and C comments as well as
+//! backslash-newline continuations are only recognised on directive lines,
+//! so `https://` URLs and shell continuations in the text survive.
+
+use std::{
+    collections::HashMap,
+    io::BufRead,
+    path::{Path, PathBuf},
+};
+
+use common::{res_err, res_ok, MyResult};
+use itertools::Itertools;
+use simple_error::simple_error;
+
+/// Nesting limit for `#include`
+const MAX_INCLUDE_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Macro {
+    /// `None` for object-like macros
+    pub params: Option<Vec<String>>,
+    pub body: String,
+}
+
+impl Macro {
+    /// Parses what follows `#define`: `NAME body` or `NAME(a, b) body`
+    fn parse(src: &str) -> MyResult<(String, Self)> {
+        let src = src.trim_start();
+        let name_len = ident_len(src);
+        if name_len == 0 {
+            return res_err(simple_error!("Macro name expected in {:?}", src));
+        }
+        let (name, rest) = src.split_at(name_len);
+        // Only a `(` right after the name makes a function-like macro
+        let (params, body) = match rest.strip_prefix('(') {
+            Some(rest) => {
+                let (params, body) = rest.split_once(')')
+                    .ok_or_else(|| simple_error!("Missing ')' in parameter list of macro {}", name))?;
+                let params = params.split(',')
+                    .map(str::trim)
+                    .filter(|p| !p.is_empty())
+                    .map(str::to_string)
+                    .collect::<Vec<_>>();
+                let bad_param = params.iter().enumerate().find(|(idx, p)| {
+                    let is_variadic = *p == "..." && *idx + 1 == params.len();
+                    !is_variadic && ident_len(p) != p.len()
+                });
+                if let Some((_, p)) = bad_param {
+                    return res_err(simple_error!("Invalid parameter {:?} of macro {}", p, name));
+                }
+                (Some(params), body)
+            }
+            None => (None, rest),
+        };
+        res_ok((name.to_string(), Self { params, body: body.trim().to_string() }))
+    }
+}
+
+/// Processes the directive dialect. Macros defined by one
+/// [Preprocessor::process] call stay defined for the next.
+#[derive(Debug, Default, Clone)]
+pub struct Preprocessor {
+    macros: HashMap<String, Macro>,
+    include_dirs: Vec<PathBuf>,
+}
+
+/// Where the lines being processed come from
+struct Source<'a> {
+    name: String,
+    dir: Option<PathBuf>,
+    /// Names of the files that included this one, outermost first
+    includers: &'a [String],
+}
+
+/// State of one `#if`..`#endif` group
+struct Cond {
+    /// Whether the lines of the current branch are kept
+    active: bool,
+    /// Whether some branch of the group was kept already
+    taken: bool,
+    /// Whether the enclosing group is active
+    parent: bool,
+    seen_else: bool,
+}
+
+impl Preprocessor {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    /// Defines a macro the way `-D` does: `NAME` (defined as `1`),
+    /// `NAME=VALUE` or `NAME(a,b)=BODY`
+    pub fn define(&mut self, definition: &str) -> MyResult<&mut Self> {
+        let src = match definition.split_once('=') {
+            Some((lhs, rhs)) => format!("{} {}", lhs, rhs),
+            None => format!("{} 1", definition),
+        };
+        let (name, mac) = Macro::parse(&src)?;
+        self.macros.insert(name, mac);
+        res_ok(self)
+    }
+    /// Defines an object-like macro expanding to `body` verbatim
+    pub fn define_object<S: Into<String>>(&mut self, name: S, body: S) -> &mut Self {
+        self.macros.insert(name.into(), Macro { params: None, body: body.into() });
+        self
+    }
+    pub fn undefine(&mut self, name: &str) -> &mut Self {
+        self.macros.remove(name);
+        self
+    }
+    pub fn is_defined(&self, name: &str) -> bool {
+        self.macros.contains_key(name)
+    }
+    /// Adds a directory searched by `#include`, after the including file's own
+    pub fn include_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Self {
+        self.include_dirs.push(dir.as_ref().to_path_buf());
+        self
+    }
+    /// Preprocesses `input`. `path`, if known, names the input in
+    /// errors and anchors relative `#include "..."`s.
+    pub fn process<R: BufRead>(&mut self, mut input: R, path: Option<&Path>) -> MyResult<String> {
+        let mut text = String::new();
+        input.read_to_string(&mut text)?;
+        let source = Source {
+            name: path.map_or_else(|| "<input>".to_string(), |p| p.display().to_string()),
+            dir: path.and_then(Path::parent).map(Path::to_path_buf),
+            includers: &[],
+        };
+        let mut out = String::with_capacity(text.len());
+        self.run(&text, &source, &mut out)?;
+        res_ok(out)
+    }
+    pub fn process_file<P: AsRef<Path>>(&mut self, path: P) -> MyResult<String> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path)
+            .map_err(|e| simple_error!("Cannot open {}: {}", path.display(), e))?;
+        self.process(std::io::BufReader::new(file), Some(path))
+    }
+
+    fn run(&mut self, text: &str, source: &Source, out: &mut String) -> MyResult<()> {
+        let mut conds: Vec<Cond> = Vec::new();
+        // Consecutive kept text lines, expanded together so that macro
+        // arguments may span lines
+        let mut pending = String::new();
+        let mut lines = text.split_inclusive('\n').enumerate();
+        while let Some((idx, line)) = lines.next() {
+            let lineno = idx + 1;
+            let active = conds.last().is_none_or(|c| c.active);
+            let Some((name, rest)) = directive(line) else {
+                if active {
+                    pending.push_str(line);
+                }
+                continue;
+            };
+            // join backslash-newline continuations
+            let mut rest = rest.to_string();
+            while rest.trim_end_matches(['\n', '\r']).ends_with('\\') {
+                rest.truncate(rest.trim_end_matches(['\n', '\r']).len() - 1);
+                match lines.next() {
+                    Some((_, next)) => rest.push_str(next),
+                    None => break,
+                }
+            }
+            let rest = strip_comments(&rest);
+            let rest = rest.trim();
+            let at = |e: common::AnyErr| simple_error!("{}:{}: {}", source.name, lineno, e);
+            out.push_str(&self.expand(&pending, &mut Vec::new()).map_err(at)?);
+            pending.clear();
+            self.directive(name, rest, active, &mut conds, source, out).map_err(at)?;
+        }
+        out.push_str(&self.expand(&pending, &mut Vec::new())
+            .map_err(|e| simple_error!("{}: {}", source.name, e))?);
+        if !conds.is_empty() {
+            return res_err(simple_error!("{}: {} unterminated #if group(s) at end of file", source.name, conds.len()));
+        }
+        res_ok(())
+    }
+
+    fn directive(&mut self, name: &str, rest: &str, active: bool, conds: &mut Vec<Cond>, source: &Source, out: &mut String) -> MyResult<()> {
+        let last_cond = |conds: &mut Vec<Cond>| conds.pop()
+            .ok_or_else(|| simple_error!("#{} without #if", name));
+        match name {
+            "ifdef" | "ifndef" | "if" => {
+                let holds = active && match name {
+                    "ifdef" => self.is_defined(macro_name(rest)?),
+                    "ifndef" => !self.is_defined(macro_name(rest)?),
+                    _ => self.condition(rest)?,
+                };
+                conds.push(Cond { active: holds, taken: holds, parent: active, seen_else: false });
+            }
+            "elif" => {
+                let mut cond = last_cond(conds)?;
+                if cond.seen_else {
+                    return res_err(simple_error!("#elif after #else"));
+                }
+                cond.active = cond.parent && !cond.taken && self.condition(rest)?;
+                cond.taken |= cond.active;
+                conds.push(cond);
+            }
+            "else" => {
+                let mut cond = last_cond(conds)?;
+                if cond.seen_else {
+                    return res_err(simple_error!("#else after #else"));
+                }
+                cond.active = cond.parent && !cond.taken;
+                cond.taken = true;
+                cond.seen_else = true;
+                conds.push(cond);
+            }
+            "endif" => {
+                last_cond(conds)?;
+            }
+            // Everything else only matters in kept regions
+            _ if !active => {}
+            // null directive, e.g. `#// comment`
+            "" => {}
+            "define" => {
+                let (name, mac) = Macro::parse(rest)?;
+                self.macros.insert(name, mac);
+            }
+            "undef" => {
+                self.macros.remove(macro_name(rest)?);
+            }
+            "error" => return res_err(simple_error!("#error {}", rest)),
+            "warning" => log::warn!("{}: #warning {}", source.name, rest),
+            "pragma" | "line" => {}
+            "include" => self.include(rest, source, out)?,
+            _ => return res_err(simple_error!("Invalid directive #{}", name)),
+        }
+        res_ok(())
+    }
+
+    fn include(&mut self, rest: &str, source: &Source, out: &mut String) -> MyResult<()> {
+        let target = if rest.starts_with('"') || rest.starts_with('<') {
+            rest.to_string()
+        } else {
+            self.expand(rest, &mut Vec::new())?.trim().to_string()
+        };
+        let (quoted, file) = if let Some(file) = target.strip_prefix('"').and_then(|t| t.strip_suffix('"')) {
+            (true, file)
+        } else if let Some(file) = target.strip_prefix('<').and_then(|t| t.strip_suffix('>')) {
+            (false, file)
+        } else {
+            return res_err(simple_error!("#include expects \"FILE\" or <FILE>, got {:?}", target));
+        };
+        let local = quoted.then(|| source.dir.as_ref().map_or_else(|| PathBuf::from(file), |dir| dir.join(file)));
+        let path = local.into_iter()
+            .chain(self.include_dirs.iter().map(|dir| dir.join(file)))
+            .find(|candidate| candidate.is_file())
+            .ok_or_else(|| simple_error!("Cannot find included file {:?}", file))?;
+        let includers = source.includers.iter().cloned()
+            .chain(std::iter::once(source.name.clone()))
+            .collect::<Vec<_>>();
+        if includers.len() > MAX_INCLUDE_DEPTH {
+            return res_err(simple_error!(
+                "#include nested too deeply: {} -> {}",
+                includers.iter().join(" -> "),
+                path.display()
+            ));
+        }
+        let text = std::fs::read_to_string(&path)
+            .map_err(|e| simple_error!("Cannot read {}: {}", path.display(), e))?;
+        let nested = Source {
+            name: path.display().to_string(),
+            dir: path.parent().map(Path::to_path_buf),
+            includers: &includers,
+        };
+        self.run(&text, &nested, out)
+    }
+
+    /// Evaluates the expression of `#if`/`#elif`
+    fn condition(&self, expr: &str) -> MyResult<bool> {
+        // `defined` is resolved before macro expansion
+        let mut resolved = String::with_capacity(expr.len());
+        let mut rest = expr;
+        while let Some(c) = rest.chars().next() {
+            let len = ident_len(rest);
+            if len == 0 {
+                resolved.push(c);
+                rest = &rest[c.len_utf8()..];
+                continue;
+            }
+            let (ident, after) = rest.split_at(len);
+            rest = after;
+            if ident != "defined" {
+                resolved.push_str(ident);
+                continue;
+            }
+            let after = rest.trim_start();
+            let (name, after) = match after.strip_prefix('(') {
+                Some(inner) => {
+                    let (name, after) = inner.split_once(')')
+                        .ok_or_else(|| simple_error!("Missing ')' after defined"))?;
+                    (name.trim(), after)
+                }
+                None => after.split_at(ident_len(after)),
+            };
+            if name.is_empty() {
+                return res_err(simple_error!("Macro name expected after defined"));
+            }
+            resolved.push_str(if self.is_defined(name) { " 1 " } else { " 0 " });
+            rest = after;
+        }
+        let expanded = self.expand(&resolved, &mut Vec::new())?;
+        eval_condition(&expanded).map(|v| v != 0)
+    }
+
+    /// Expands every macro invocation in `text`. `disabled` holds the macros
+    /// being expanded, which are not expanded again.
+    fn expand(&self, text: &str, disabled: &mut Vec<String>) -> MyResult<String> {
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(c) = rest.chars().next() {
+            let len = match c {
+                '"' | '\'' => quoted_len(rest),
+                c if c.is_ascii_digit() => rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+                    .unwrap_or(rest.len()),
+                _ => ident_len(rest),
+            };
+            if len == 0 || !is_ident_start(c) {
+                let len = len.max(c.len_utf8());
+                out.push_str(&rest[..len]);
+                rest = &rest[len..];
+                continue;
+            }
+            let (name, after) = rest.split_at(len);
+            rest = after;
+            let mac = match self.macros.get(name) {
+                Some(mac) if !disabled.iter().any(|d| d == name) => mac,
+                _ => {
+                    out.push_str(name);
+                    continue;
+                }
+            };
+            let body = match &mac.params {
+                None => paste(&[Piece::Text(mac.body.clone())]),
+                Some(params) => match call_args(rest)? {
+                    // A function-like macro name without arguments stays as is
+                    None => {
+                        out.push_str(name);
+                        continue;
+                    }
+                    Some((args, consumed)) => {
+                        rest = &rest[consumed..];
+                        self.substitute(name, mac, params, args, disabled)?
+                    }
+                },
+            };
+            disabled.push(name.to_string());
+            let expanded = self.expand(&body, disabled);
+            disabled.pop();
+            out.push_str(&expanded?);
+        }
+        res_ok(out)
+    }
+
+    /// Replaces the parameters in the body of a function-like macro
+    fn substitute(&self, name: &str, mac: &Macro, params: &[String], args: Vec<&str>, disabled: &mut Vec<String>) -> MyResult<String> {
+        let variadic = params.last().is_some_and(|p| p == "...");
+        let fixed = params.len() - variadic as usize;
+        // `F()` passes a single empty argument
+        let args = if params.is_empty() && args.len() == 1 && args[0].trim().is_empty() {
+            Vec::new()
+        } else {
+            args
+        };
+        if args.len() < fixed || (!variadic && args.len() != fixed) {
+            return res_err(simple_error!("Macro {} expects {} argument(s), got {}", name, fixed, args.len()));
+        }
+        let mut bound = params[..fixed].iter()
+            .map(String::as_str)
+            .zip(args.iter().map(|a| a.trim().to_string()))
+            .collect::<HashMap<_, _>>();
+        if variadic {
+            bound.insert("__VA_ARGS__", args[fixed..].iter().map(|a| a.trim()).join(","));
+        }
+        let pieces = lex_body(&mac.body);
+        let significant = |idx: usize, step: isize| {
+            let mut idx = idx as isize + step;
+            while let Some(piece) = pieces.get(idx as usize).filter(|_| idx >= 0) {
+                if !matches!(piece, Piece::Space(_)) {
+                    return Some(piece);
+                }
+                idx += step;
+            }
+            None
+        };
+        let mut out = Vec::with_capacity(pieces.len());
+        let mut skip_next_param = false;
+        for (idx, piece) in pieces.iter().enumerate() {
+            match piece {
+                Piece::Hash => match significant(idx, 1) {
+                    Some(Piece::Ident(param)) if bound.contains_key(param) => {
+                        out.push(Piece::Text(stringize(&bound[param])));
+                        skip_next_param = true;
+                    }
+                    _ => out.push(Piece::Text("#".to_string())),
+                },
+                Piece::Space(_) if skip_next_param => {}
+                Piece::Ident(param) if bound.contains_key(param) => {
+                    if std::mem::take(&mut skip_next_param) {
+                        continue;
+                    }
+                    let next_to_paste = matches!(significant(idx, -1), Some(Piece::Paste))
+                        || matches!(significant(idx, 1), Some(Piece::Paste));
+                    out.push(Piece::Text(if next_to_paste {
+                        bound[param].clone()
+                    } else {
+                        self.expand(&bound[param], disabled)?
+                    }));
+                }
+                piece => out.push(piece.clone()),
+            }
+        }
+        res_ok(paste(&out))
+    }
+}
+
+/// Lexical pieces of a macro body
+#[derive(Debug, Clone)]
+enum Piece<'a> {
+    Ident(&'a str),
+    /// `#`, the stringizing operator
+    Hash,
+    /// `##`, the pasting operator
+    Paste,
+    Space(&'a str),
+    Other(&'a str),
+    Text(String),
+}
+
+fn lex_body(body: &str) -> Vec<Piece<'_>> {
+    let mut pieces = Vec::new();
+    let mut rest = body;
+    while let Some(c) = rest.chars().next() {
+        let (piece, len) = if rest.starts_with("##") {
+            (Piece::Paste, 2)
+        } else if c == '#' {
+            (Piece::Hash, 1)
+        } else if c.is_whitespace() {
+            let len = rest.find(|c: char| !c.is_whitespace()).unwrap_or(rest.len());
+            (Piece::Space(&rest[..len]), len)
+        } else if c == '"' || c == '\'' {
+            let len = quoted_len(rest);
+            (Piece::Other(&rest[..len]), len)
+        } else if is_ident_start(c) {
+            let len = ident_len(rest);
+            (Piece::Ident(&rest[..len]), len)
+        } else {
+            (Piece::Other(&rest[..c.len_utf8()]), c.len_utf8())
+        };
+        pieces.push(piece);
+        rest = &rest[len..];
+    }
+    pieces
+}
+
+/// Renders pieces, gluing together the operands of `##`
+fn paste(pieces: &[Piece]) -> String {
+    let mut out = String::new();
+    let mut glue = false;
+    for piece in pieces {
+        let text = match piece {
+            Piece::Paste => {
+                out.truncate(out.trim_end().len());
+                glue = true;
+                continue;
+            }
+            Piece::Space(_) if glue => continue,
+            Piece::Ident(s) | Piece::Space(s) | Piece::Other(s) => s,
+            Piece::Hash => "#",
+            Piece::Text(s) => s.as_str(),
+        };
+        out.push_str(if std::mem::take(&mut glue) { text.trim_start() } else { text });
+    }
+    out
+}
+
+fn stringize(arg: &str) -> String {
+    let collapsed = arg.split_whitespace().join(" ");
+    format!("\"{}\"", collapsed.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Splits the arguments of a macro invocation, if `rest` starts with one.
+/// Returns the raw arguments and how many bytes the invocation spans.
+fn call_args(rest: &str) -> MyResult<Option<(Vec<&str>, usize)>> {
+    let start = rest.len() - rest.trim_start().len();
+    if !rest[start..].starts_with('(') {
+        return res_ok(None);
+    }
+    let mut args = Vec::new();
+    let (mut depth, mut arg_start, mut idx) = (0usize, start + 1, start + 1);
+    while let Some(c) = rest[idx..].chars().next() {
+        match c {
+            '"' | '\'' => {
+                idx += quoted_len(&rest[idx..]);
+                continue;
+            }
+            '(' => depth += 1,
+            ')' if depth == 0 => {
+                args.push(&rest[arg_start..idx]);
+                return res_ok(Some((args, idx + 1)));
+            }
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                args.push(&rest[arg_start..idx]);
+                arg_start = idx + 1;
+            }
+            _ => {}
+        }
+        idx += c.len_utf8();
+    }
+    res_err(simple_error!("Unterminated macro argument list"))
+}
+
+/// Splits a directive line into its name and the rest, or `None` for text
+fn directive(line: &str) -> Option<(&str, &str)> {
+    let body = line.trim_start().strip_prefix('#')?.trim_start();
+    Some(body.split_at(ident_len(body)))
+}
+
+fn macro_name(rest: &str) -> MyResult<&str> {
+    let len = ident_len(rest);
+    if len == 0 || !rest[len..].trim().is_empty() {
+        return res_err(simple_error!("Macro name expected, got {:?}", rest));
+    }
+    res_ok(&rest[..len])
+}
+
+/// Removes `//` and `/* */` comments outside of quotes
+fn strip_comments(src: &str) -> String {
+    let mut out = String::with_capacity(src.len());
+    let mut rest = src;
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("//") {
+            break;
+        }
+        if rest.starts_with("/*") {
+            rest = rest[2..].split_once("*/").map_or("", |(_, after)| after);
+            out.push(' ');
+            continue;
+        }
+        let len = if c == '"' || c == '\'' { quoted_len(rest) } else { c.len_utf8() };
+        out.push_str(&rest[..len]);
+        rest = &rest[len..];
+    }
+    out
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+/// Length of the identifier `src` starts with, `0` if none
+fn ident_len(src: &str) -> usize {
+    if !src.starts_with(is_ident_start) {
+        return 0;
+    }
+    src.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(src.len())
+}
+
+/// Length of the quoted literal `src` starts with. An unterminated quote,
+/// e.g. the apostrophe in `isn't`, only spans itself.
+fn quoted_len(src: &str) -> usize {
+    let quote = src.chars().next().expect("quoted_len expects a quote");
+    let mut escaped = false;
+    for (idx, c) in src.char_indices().skip(1) {
+        match c {
+            '\n' => break,
+            '\\' if !escaped => escaped = true,
+            c if c == quote && !escaped => return idx + 1,
+            _ => escaped = false,
+        }
+    }
+    1
+}
+
+/// Evaluates a fully expanded `#if` expression over `i64`.
+/// Identifiers that survive expansion count as `0`.
+fn eval_condition(expr: &str) -> MyResult<i64> {
+    let tokens = lex_condition(expr)?;
+    let mut parser = CondParser { tokens: &tokens, pos: 0 };
+    let value = parser.binary(0)?;
+    match parser.tokens.get(parser.pos) {
+        None => res_ok(value),
+        Some(tok) => res_err(simple_error!("Unexpected {:?} in #if expression {:?}", tok, expr.trim())),
+    }
+}
+
+fn lex_condition(expr: &str) -> MyResult<Vec<String>> {
+    const OPERATORS: [&str; 18] = [
+        "||", "&&", "==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "%", "!", "(", ")", "~", "?",
+    ];
+    let mut tokens = Vec::new();
+    let mut rest = expr.trim_start();
+    while !rest.is_empty() {
+        let len = if rest.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_') {
+            rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len())
+        } else {
+            OPERATORS.iter()
+                .find(|op| rest.starts_with(**op))
+                .map(|op| op.len())
+                .ok_or_else(|| simple_error!("Unexpected character in #if expression {:?}", expr.trim()))?
+        };
+        tokens.push(rest[..len].to_string());
+        rest = rest[len..].trim_start();
+    }
+    res_ok(tokens)
+}
+
+struct CondParser<'a> {
+    tokens: &'a [String],
+    pos: usize,
+}
+
+impl<'a> CondParser<'a> {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(String::as_str)
+    }
+    /// Precedence climbing over the binary operators
+    fn binary(&mut self, min_prec: u8) -> MyResult<i64> {
+        let mut lhs = self.unary()?;
+        while let Some(op) = self.peek() {
+            let prec = match op {
+                "||" => 1,
+                "&&" => 2,
+                "==" | "!=" => 3,
+                "<" | ">" | "<=" | ">=" => 4,
+                "+" | "-" => 5,
+                "*" | "/" | "%" => 6,
+                _ => break,
+            };
+            if prec < min_prec {
+                break;
+            }
+            let op = op.to_string();
+            self.pos += 1;
+            let rhs = self.binary(prec + 1)?;
+            let overflow = || simple_error!("Integer overflow in #if expression");
+            lhs = match op.as_str() {
+                "||" => (lhs != 0 || rhs != 0) as i64,
+                "&&" => (lhs != 0 && rhs != 0) as i64,
+                "==" => (lhs == rhs) as i64,
+                "!=" => (lhs != rhs) as i64,
+                "<" => (lhs < rhs) as i64,
+                ">" => (lhs > rhs) as i64,
+                "<=" => (lhs <= rhs) as i64,
+                ">=" => (lhs >= rhs) as i64,
+                "+" => lhs.checked_add(rhs).ok_or_else(overflow)?,
+                "-" => lhs.checked_sub(rhs).ok_or_else(overflow)?,
+                "*" => lhs.checked_mul(rhs).ok_or_else(overflow)?,
+                "/" | "%" if rhs == 0 => return res_err(simple_error!("Division by zero in #if expression")),
+                "/" => lhs.checked_div(rhs).ok_or_else(overflow)?,
+                _ => lhs.checked_rem(rhs).ok_or_else(overflow)?,
+            };
+        }
+        res_ok(lhs)
+    }
+    fn unary(&mut self) -> MyResult<i64> {
+        let tok = self.peek()
+            .ok_or_else(|| simple_error!("Unexpected end of #if expression"))?
+            .to_string();
+        self.pos += 1;
+        match tok.as_str() {
+            "!" => self.unary().map(|v| (v == 0) as i64),
+            "-" => self.unary().and_then(|v| v.checked_neg().ok_or_else(|| simple_error!("Integer overflow in #if expression").into())),
+            "+" => self.unary(),
+            "~" => self.unary().map(|v| !v),
+            "(" => {
+                let value = self.binary(0)?;
+                if self.peek() != Some(")") {
+                    return res_err(simple_error!("Missing ')' in #if expression"));
+                }
+                self.pos += 1;
+                res_ok(value)
+            }
+            tok if tok.starts_with(|c: char| c.is_ascii_digit()) => parse_int(tok),
+            tok if tok.starts_with(is_ident_start) => res_ok(0),
+            tok => res_err(simple_error!("Unexpected {:?} in #if expression", tok)),
+        }
+    }
+}
+
+/// Parses a C integer literal: decimal, `0x` hex or `0` octal, with an
+/// optional `u`/`l` suffix
+fn parse_int(tok: &str) -> MyResult<i64> {
+    let digits = tok.trim_end_matches(['u', 'U', 'l', 'L']);
+    let parsed = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16)
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        i64::from_str_radix(&digits[1..], 8)
+    } else {
+        digits.parse()
+    };
+    parsed.map_err(|_| simple_error!("Invalid integer {:?} in #if expression", tok).into())
+}
@@ -0,0 +1,206 @@
+//! Context-aware escaping of substituted values
+
+use std::{borrow::Cow, fmt::{self, Write}, path::Path, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use simple_error::{simple_error, SimpleError};
+
+/// How a value is made safe for the surrounding output format.
+///
+/// `Json` and `Yaml` escape the value for a double-quoted string, which
+/// the template writes around it, as in `"greeting": "hello ${name}"`.
+/// A placeholder outside such a string, as in `"port": ${port}`, gets a
+/// complete quoted value instead, unless the value is a plain number,
+/// boolean or null. `Shell` produces a complete quoted word, and `Xml` and
+/// `Url` encode the value in place.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EscapeMode {
+    /// Substitute the value verbatim
+    None,
+    Json,
+    Yaml,
+    Shell,
+    Xml,
+    Url,
+}
+
+impl EscapeMode {
+    /// Infers the mode from the extension of an output (or template) path,
+    /// e.g. `config.t.json` escapes for JSON
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let ext = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "json" => Some(Self::Json),
+            "yml" | "yaml" => Some(Self::Yaml),
+            "sh" | "bash" | "zsh" => Some(Self::Shell),
+            "xml" | "html" | "htm" | "xhtml" | "svg" => Some(Self::Xml),
+            _ => None,
+        }
+    }
+    /// Escapes a value written after `output`, of which the text after the
+    /// last newline tells whether a `Json` or `Yaml` value lands inside a
+    /// double-quoted string
+    pub fn escape_at<'a>(&self, value: Cow<'a, str>, output: &[u8]) -> Cow<'a, str> {
+        match self {
+            Self::Json | Self::Yaml if !in_quotes(output) && !is_plain_scalar(&value) => match self {
+                Self::Json => serde_json::to_string(value.as_ref()).expect("Serializing a str cannot fail").into(),
+                _ => yaml_quote(&value).into(),
+            },
+            Self::Json | Self::Yaml if !in_quotes(output) => value,
+            _ => self.escape(value),
+        }
+    }
+    /// Escapes a value for the inside of a double-quoted string, see [Self::escape_at]
+    pub fn escape<'a>(&self, value: Cow<'a, str>) -> Cow<'a, str> {
+        match self {
+            Self::None => value,
+            Self::Json => {
+                let quoted = serde_json::to_string(value.as_ref()).expect("Serializing a str cannot fail");
+                quoted[1..quoted.len() - 1].to_string().into()
+            }
+            Self::Yaml => yaml_escape(&value).into(),
+            Self::Shell => shell_quote(value),
+            Self::Xml => xml_escape(value),
+            Self::Url => url_encode(value),
+        }
+    }
+}
+
+impl fmt::Display for EscapeMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::None => "none",
+            Self::Json => "json",
+            Self::Yaml => "yaml",
+            Self::Shell => "shell",
+            Self::Xml => "xml",
+            Self::Url => "url",
+        })
+    }
+}
+
+impl FromStr for EscapeMode {
+    type Err = SimpleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" | "raw" => Ok(Self::None),
+            "json" => Ok(Self::Json),
+            "yaml" | "yml" => Ok(Self::Yaml),
+            "shell" | "sh" => Ok(Self::Shell),
+            "xml" | "html" => Ok(Self::Xml),
+            "url" => Ok(Self::Url),
+            _ => Err(simple_error!(
+                "Unknown escape mode {:?}, expected one of none, json, yaml, shell, xml, url",
+                s
+            )),
+        }
+    }
+}
+
+/// Whether the line `output` ends with has an unclosed double-quoted string
+fn in_quotes(output: &[u8]) -> bool {
+    let line = match memchr::memrchr(b'\n', output) {
+        Some(nl) => &output[nl + 1..],
+        None => output,
+    };
+    let (mut quoted, mut escaped) = (false, false);
+    for &b in line {
+        match b {
+            b'\\' if quoted => {
+                escaped = !escaped;
+                continue;
+            }
+            b'"' if !escaped => quoted = !quoted,
+            _ => {}
+        }
+        escaped = false;
+    }
+    quoted
+}
+
+/// A number, boolean or null, which both JSON and YAML read as a single scalar
+fn is_plain_scalar(value: &str) -> bool {
+    value.trim() == value
+        && matches!(serde_json::from_str(value), Ok(serde_json::Value::Number(_) | serde_json::Value::Bool(_) | serde_json::Value::Null))
+}
+
+/// YAML double-quoted scalar. Line breaks and other non-printables
+/// are escaped so that the scalar never spans lines.
+pub(crate) fn yaml_quote(value: &str) -> String {
+    format!("\"{}\"", yaml_escape(value))
+}
+
+/// The inside of [yaml_quote]
+fn yaml_escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{85}' => out.push_str("\\N"),
+            '\u{2028}' => out.push_str("\\L"),
+            '\u{2029}' => out.push_str("\\P"),
+            c if c.is_control() || c == '\u{feff}' => {
+                write!(out, "\\u{:04x}", c as u32).expect("Writing to a String cannot fail")
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// POSIX shell word: left alone when it only holds safe characters,
+/// single-quoted otherwise
+fn shell_quote(value: Cow<'_, str>) -> Cow<'_, str> {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c);
+    if !value.is_empty() && value.chars().all(is_safe) {
+        value
+    } else {
+        format!("'{}'", value.replace('\'', r"'\''")).into()
+    }
+}
+
+/// Entity-escapes markup characters. Characters that XML 1.0 cannot
+/// represent at all are replaced with U+FFFD.
+fn xml_escape(value: Cow<'_, str>) -> Cow<'_, str> {
+    let needs_escape =
+        |c: char| matches!(c, '&' | '<' | '>' | '"' | '\'') || (c.is_control() && !matches!(c, '\t' | '\n' | '\r'));
+    if !value.chars().any(needs_escape) {
+        return value;
+    }
+    let mut out = String::with_capacity(value.len() + 8);
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c if needs_escape(c) => out.push('\u{fffd}'),
+            c => out.push(c),
+        }
+    }
+    out.into()
+}
+
+/// Percent-encodes everything but RFC 3986 unreserved characters
+fn url_encode(value: Cow<'_, str>) -> Cow<'_, str> {
+    let is_unreserved = |b: &u8| b.is_ascii_alphanumeric() || b"-._~".contains(b);
+    if value.bytes().all(|b| is_unreserved(&b)) {
+        return value;
+    }
+    let mut out = String::with_capacity(value.len() * 3);
+    for b in value.bytes() {
+        if is_unreserved(&b) {
+            out.push(b as char);
+        } else {
+            write!(out, "%{:02X}", b).expect("Writing to a String cannot fail");
+        }
+    }
+    out.into()
+}
@@ -0,0 +1,139 @@
+//! Recursive expansion of variable definitions that reference other variables,
+//! e.g. `"base_url": "https://${host}:${port}"`
+//!
+//! Only `${...}` is special in a definition, and `\${` writes it literally:
+//! a lone `$`, `$#` or backslash is text, as is a `${` that is never closed.
+
+use std::{borrow::Cow, io::Cursor};
+
+use common::{res_err, res_ok, MyResult};
+use itertools::Itertools;
+use simple_error::simple_error;
+
+use crate::{apply_filters, is_builtin, parse_template, RenderBuiltins, TemplateTrait, Token, VariableTrait};
+
+/// Nesting limit for callers that opt into expansion through
+/// [crate::GenerateOptions::max_expand_depth], which defaults to `0`
+pub const DEFAULT_EXPAND_DEPTH: usize = 16;
+
+/// Wraps any [VariableTrait] so that `${other}` references inside a
+/// definition are substituted with the definition of `other`.
+///
+/// Cycles are reported as the chain of names involved, i.e. `a -> b -> a`.
+pub struct ExpandVariables<'v, 'b, V: VariableTrait> {
+    variables: &'v V,
+    max_depth: usize,
+    builtins: Option<&'b RenderBuiltins<'b>>,
+}
+
+impl<'v, 'b, V: VariableTrait> ExpandVariables<'v, 'b, V> {
+    pub fn new(variables: &'v V, max_depth: usize) -> Self {
+        Self { variables, max_depth, builtins: None }
+    }
+    /// Resolves `la.*` references with `builtins` rather than the variables
+    pub fn with_builtins(mut self, builtins: &'b RenderBuiltins<'b>) -> Self {
+        self.builtins = Some(builtins);
+        self
+    }
+    /// Expanded definition of `key`
+    pub fn expand(&self, key: &str) -> MyResult<Cow<'v, str>> {
+        self.expand_chain(key, &mut Vec::new())
+    }
+    fn expand_chain(&self, key: &str, chain: &mut Vec<String>) -> MyResult<Cow<'v, str>> {
+        if let Some(start) = chain.iter().position(|k| k == key) {
+            return res_err(simple_error!(
+                "Cyclic variable reference: {} -> {}",
+                chain[start..].iter().join(" -> "),
+                key
+            ));
+        }
+        if let Some(builtins) = self.builtins.filter(|_| is_builtin(key)) {
+            return builtins.get(key).map(Cow::Owned);
+        }
+        let defn = self.variables.get_defn(key)?;
+        if !defn.contains("${") {
+            return res_ok(defn);
+        }
+        if chain.len() >= self.max_depth {
+            return res_err(simple_error!(
+                "Variable expansion exceeds depth limit of {}: {} -> {}",
+                self.max_depth,
+                chain.iter().join(" -> "),
+                key
+            ));
+        }
+        chain.push(key.to_string());
+        let expanded = references(&defn)
+            .into_iter()
+            .map(|piece| match piece {
+                Piece::Text(text) => res_ok(Cow::from(text)),
+                Piece::Reference(src) => self.expand_reference(key, src, chain).map(Cow::Owned),
+            })
+            .collect::<MyResult<String>>();
+        chain.pop();
+        expanded.map(Cow::Owned)
+    }
+    /// The value of the `${...}` construct `src` in the definition of `key`
+    fn expand_reference(&self, key: &str, src: &str, chain: &mut Vec<String>) -> MyResult<String> {
+        let template = parse_template(Cursor::new(src.as_bytes()))
+            .map_err(|e| simple_error!("Failed to parse {} in definition of {}: {}", src, key, e))?;
+        template.tokens().iter()
+            .map(|tok| match tok {
+                Token::Str(s) => res_ok(Cow::from(s.as_str())),
+                Token::Bytes(_) => res_err(simple_error!("Binary literal in definition of {}", key)),
+                Token::Var(idx) => self.expand_chain(&template.symbols()[*idx as usize], chain),
+                Token::Subst(subst) => self.expand_chain(&template.symbols()[subst.var as usize], chain)
+                    .and_then(|value| apply_filters(&subst.filters, value)),
+                Token::Expr(expression) => expression.expr
+                    .eval(&mut |var| self.expand_chain(&template.symbols()[var as usize], chain))
+                    .and_then(|value| apply_filters(&expression.filters, value.to_string().into())),
+                Token::Tag(tag) => res_err(simple_error!("Unsupported block tag {:?} in definition of {}", tag.name, key)),
+            })
+            .collect()
+    }
+}
+
+/// Part of a definition, see [references]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Piece<'d> {
+    Text(&'d str),
+    /// A whole `${...}` construct
+    Reference(&'d str),
+}
+
+/// Splits a definition into text and the `${...}` constructs it references
+/// variables with, see the module docs
+pub(crate) fn references(defn: &str) -> Vec<Piece<'_>> {
+    let mut pieces = Vec::new();
+    let mut rest = defn;
+    while let Some(at) = rest.find("${") {
+        if rest[..at].ends_with('\\') {
+            pieces.push(Piece::Text(&rest[..at - 1]));
+            pieces.push(Piece::Text("${"));
+            rest = &rest[at + 2..];
+            continue;
+        }
+        let Some(len) = rest[at..].find('}') else { break };
+        pieces.push(Piece::Text(&rest[..at]));
+        pieces.push(Piece::Reference(&rest[at..at + len + 1]));
+        rest = &rest[at + len + 1..];
+    }
+    pieces.push(Piece::Text(rest));
+    pieces.retain(|piece| *piece != Piece::Text(""));
+    pieces
+}
+
+impl<'v, V: VariableTrait> VariableTrait for ExpandVariables<'v, '_, V> {
+    fn _get_defn<'a>(&'a self, key: &str) -> MyResult<Cow<'a, str>> {
+        self.expand(key)
+    }
+    fn defines(&self, key: &str) -> bool {
+        self.variables.defines(key)
+    }
+    fn names(&self) -> Option<Vec<&str>> {
+        self.variables.names()
+    }
+    fn get_json(&self, key: &str) -> Option<&serde_json::Value> {
+        self.variables.get_json(key)
+    }
+}
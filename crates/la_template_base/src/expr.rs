@@ -0,0 +1,466 @@
+//! Expressions inside substitutions, e.g. `${replicas * 2}` or `${count > 3}`.
+//!
+//! The language is sandboxed: literals, variables, arithmetic, comparisons and
+//! boolean logic, no calls. Variables whose definition reads as a number or a
+//! bool take part as one, others as strings. Integer arithmetic is checked and
+//! mixing types without an obvious meaning is an error.
+
+use std::{borrow::Cow, fmt};
+
+use common::{res_err, res_ok, MyResult};
+use serde::{Deserialize, Serialize};
+use simple_error::simple_error;
+
+use crate::{filter::closing_quote, Filter};
+
+/// How deep parentheses and unary operators may nest
+const MAX_DEPTH: usize = 64;
+
+/// An [Expr] that goes through [Filter]s, i.e. `${port + 1 | escape("json")}`
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Expression {
+    pub expr: Expr,
+    pub filters: Vec<Filter>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub enum Expr {
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Bool(bool),
+    /// Index into [crate::TemplateTrait::symbols]
+    Var(u8),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+// Float literals are parsed from digits, so never NaN
+impl Eq for Expr {}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    /// Binding strength, higher binds tighter
+    fn precedence(self) -> u8 {
+        match self {
+            Self::Or => 1,
+            Self::And => 2,
+            Self::Eq | Self::Ne => 3,
+            Self::Lt | Self::Le | Self::Gt | Self::Ge => 4,
+            Self::Add | Self::Sub => 5,
+            Self::Mul | Self::Div | Self::Rem => 6,
+        }
+    }
+    fn symbol(self) -> &'static str {
+        match self {
+            Self::Mul => "*",
+            Self::Div => "/",
+            Self::Rem => "%",
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+            Self::Eq => "==",
+            Self::Ne => "!=",
+            Self::And => "&&",
+            Self::Or => "||",
+        }
+    }
+    const ALL: [Self; 13] = [
+        Self::Le, Self::Ge, Self::Eq, Self::Ne, Self::And, Self::Or,
+        Self::Mul, Self::Div, Self::Rem, Self::Add, Self::Sub, Self::Lt, Self::Gt,
+    ];
+}
+
+/// Result of evaluating an [Expr]
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprValue {
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Bool(bool),
+}
+
+impl ExprValue {
+    /// Reads a variable definition: `3` is an int, `2.5` a float,
+    /// `true` a bool and anything else a string
+    pub fn from_defn(defn: &str) -> Self {
+        if let Ok(int) = defn.parse() {
+            return Self::Int(int);
+        }
+        let is_decimal = defn.bytes().any(|b| b.is_ascii_digit())
+            && defn.bytes().all(|b| b.is_ascii_digit() || b"+-.eE".contains(&b));
+        match defn.parse() {
+            Ok(float) if is_decimal => Self::Float(float),
+            _ => match defn {
+                "true" => Self::Bool(true),
+                "false" => Self::Bool(false),
+                _ => Self::Str(defn.to_string()),
+            },
+        }
+    }
+    /// The value as JSON, a float that JSON cannot hold as its text
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Self::Int(int) => (*int).into(),
+            Self::Float(float) => serde_json::Number::from_f64(*float)
+                .map_or_else(|| float.to_string().into(), serde_json::Value::Number),
+            Self::Str(s) => s.as_str().into(),
+            Self::Bool(b) => (*b).into(),
+        }
+    }
+    fn type_name(&self) -> &'static str {
+        match self {
+            Self::Int(_) => "int",
+            Self::Float(_) => "float",
+            Self::Str(_) => "string",
+            Self::Bool(_) => "bool",
+        }
+    }
+    fn as_float(&self) -> Option<f64> {
+        match self {
+            Self::Int(int) => Some(*int as f64),
+            Self::Float(float) => Some(*float),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ExprValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Int(int) => write!(f, "{}", int),
+            Self::Float(float) => write!(f, "{}", float),
+            Self::Str(s) => f.write_str(s),
+            Self::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+/// `int 2` or `string "abc"`, for type errors
+fn describe(value: &ExprValue) -> String {
+    match value {
+        ExprValue::Str(s) => format!("string {:?}", s),
+        value => format!("{} {}", value.type_name(), value),
+    }
+}
+
+impl Expr {
+    /// Parses `src`, registering variable names with `intern`
+    pub fn parse<'s, F>(src: &'s str, intern: &mut F) -> MyResult<Self>
+    where
+        F: FnMut(&'s str) -> MyResult<u8>,
+    {
+        let mut parser = Parser { src, pos: 0, depth: 0, intern };
+        let expr = parser.binary(1)?;
+        parser.skip_space();
+        if parser.pos < src.len() {
+            return res_err(simple_error!("Unexpected {:?} in expression {:?}", &src[parser.pos..], src));
+        }
+        res_ok(expr)
+    }
+    /// Evaluates the expression, looking variables up with `defn_of`
+    pub fn eval<'v, F>(&self, defn_of: &mut F) -> MyResult<ExprValue>
+    where
+        F: FnMut(u8) -> MyResult<Cow<'v, str>>,
+    {
+        match self {
+            Self::Int(int) => res_ok(ExprValue::Int(*int)),
+            Self::Float(float) => res_ok(ExprValue::Float(*float)),
+            Self::Str(s) => res_ok(ExprValue::Str(s.clone())),
+            Self::Bool(b) => res_ok(ExprValue::Bool(*b)),
+            Self::Var(var) => res_ok(ExprValue::from_defn(&defn_of(*var)?)),
+            Self::Unary(op, operand) => match (op, operand.eval(defn_of)?) {
+                (UnaryOp::Neg, ExprValue::Int(int)) => int.checked_neg()
+                    .map(ExprValue::Int)
+                    .ok_or_else(|| simple_error!("Integer overflow in -{}", int).into()),
+                (UnaryOp::Neg, ExprValue::Float(float)) => res_ok(ExprValue::Float(-float)),
+                (UnaryOp::Not, ExprValue::Bool(b)) => res_ok(ExprValue::Bool(!b)),
+                (op, value) => res_err(simple_error!(
+                    "Cannot apply '{}' to {}",
+                    if *op == UnaryOp::Neg { "-" } else { "!" },
+                    describe(&value)
+                )),
+            },
+            Self::Binary(op @ (BinaryOp::And | BinaryOp::Or), lhs, rhs) => {
+                let lhs = lhs.eval(defn_of)?;
+                match lhs {
+                    ExprValue::Bool(b) if b == (*op == BinaryOp::Or) => res_ok(lhs),
+                    ExprValue::Bool(_) => match rhs.eval(defn_of)? {
+                        rhs @ ExprValue::Bool(_) => res_ok(rhs),
+                        rhs => res_err(type_error(*op, &lhs, &rhs)),
+                    },
+                    lhs => res_err(simple_error!("Cannot apply '{}' to {}", op.symbol(), describe(&lhs))),
+                }
+            }
+            Self::Binary(op, lhs, rhs) => binary(*op, lhs.eval(defn_of)?, rhs.eval(defn_of)?),
+        }
+    }
+    /// Indices of the variables referenced
+    pub fn vars(&self) -> Vec<u8> {
+        match self {
+            Self::Var(var) => vec![*var],
+            Self::Unary(_, operand) => operand.vars(),
+            Self::Binary(_, lhs, rhs) => [lhs.vars(), rhs.vars()].concat(),
+            _ => Vec::new(),
+        }
+    }
+    /// Renumbers the variables referenced
+    pub fn map_vars<F: FnMut(u8) -> u8>(&self, f: &mut F) -> Self {
+        match self {
+            Self::Var(var) => Self::Var(f(*var)),
+            Self::Unary(op, operand) => Self::Unary(*op, Box::new(operand.map_vars(f))),
+            Self::Binary(op, lhs, rhs) => Self::Binary(*op, Box::new(lhs.map_vars(f)), Box::new(rhs.map_vars(f))),
+            expr => expr.clone(),
+        }
+    }
+    /// Replaces every variable with the expression `f` gives for it
+    pub fn replace_vars<F: FnMut(u8) -> Expr>(&self, f: &mut F) -> Self {
+        match self {
+            Self::Var(var) => f(*var),
+            Self::Unary(op, operand) => Self::Unary(*op, Box::new(operand.replace_vars(f))),
+            Self::Binary(op, lhs, rhs) => Self::Binary(*op, Box::new(lhs.replace_vars(f)), Box::new(rhs.replace_vars(f))),
+            expr => expr.clone(),
+        }
+    }
+    /// Writes the expression back in template syntax
+    pub fn to_source<S: AsRef<str>>(&self, symbols: &[S]) -> MyResult<String> {
+        res_ok(match self {
+            Self::Int(int) => int.to_string(),
+            Self::Float(float) => format!("{:?}", float),
+            Self::Str(s) => serde_json::to_string(s)?,
+            Self::Bool(b) => b.to_string(),
+            Self::Var(var) => symbols.get(*var as usize)
+                .ok_or_else(|| simple_error!("Idx out of bounds: {}", var))?
+                .as_ref()
+                .to_string(),
+            Self::Unary(op, operand) => {
+                let operand = match **operand {
+                    Self::Binary(..) => format!("({})", operand.to_source(symbols)?),
+                    _ => operand.to_source(symbols)?,
+                };
+                format!("{}{}", if *op == UnaryOp::Neg { "-" } else { "!" }, operand)
+            }
+            Self::Binary(op, lhs, rhs) => {
+                // parenthesize operands that bind looser, on the right also equally loose
+                let operand = |expr: &Self, tie: bool| -> MyResult<String> {
+                    let src = expr.to_source(symbols)?;
+                    res_ok(match expr {
+                        Self::Binary(inner, ..) if inner.precedence() < op.precedence()
+                            || (tie && inner.precedence() == op.precedence()) => format!("({})", src),
+                        _ => src,
+                    })
+                };
+                format!("{} {} {}", operand(lhs, false)?, op.symbol(), operand(rhs, true)?)
+            }
+        })
+    }
+}
+
+fn type_error(op: BinaryOp, lhs: &ExprValue, rhs: &ExprValue) -> simple_error::SimpleError {
+    simple_error!("Cannot apply '{}' to {} and {}", op.symbol(), describe(lhs), describe(rhs))
+}
+
+fn binary(op: BinaryOp, lhs: ExprValue, rhs: ExprValue) -> MyResult<ExprValue> {
+    use ExprValue::*;
+    let overflow = || simple_error!("Integer overflow in {} {} {}", lhs, op.symbol(), rhs);
+    match (op, &lhs, &rhs) {
+        (BinaryOp::Div | BinaryOp::Rem, Int(_), Int(0)) => res_err(simple_error!("Division by zero in {} {} 0", lhs, op.symbol())),
+        (BinaryOp::Add, Int(a), Int(b)) => a.checked_add(*b).map(Int).ok_or_else(|| overflow().into()),
+        (BinaryOp::Sub, Int(a), Int(b)) => a.checked_sub(*b).map(Int).ok_or_else(|| overflow().into()),
+        (BinaryOp::Mul, Int(a), Int(b)) => a.checked_mul(*b).map(Int).ok_or_else(|| overflow().into()),
+        (BinaryOp::Div, Int(a), Int(b)) => a.checked_div(*b).map(Int).ok_or_else(|| overflow().into()),
+        (BinaryOp::Rem, Int(a), Int(b)) => a.checked_rem(*b).map(Int).ok_or_else(|| overflow().into()),
+        (BinaryOp::Add, Str(a), Str(b)) => res_ok(Str(format!("{}{}", a, b))),
+        (BinaryOp::Eq, Str(a), Str(b)) => res_ok(Bool(a == b)),
+        (BinaryOp::Ne, Str(a), Str(b)) => res_ok(Bool(a != b)),
+        (BinaryOp::Lt, Str(a), Str(b)) => res_ok(Bool(a < b)),
+        (BinaryOp::Le, Str(a), Str(b)) => res_ok(Bool(a <= b)),
+        (BinaryOp::Gt, Str(a), Str(b)) => res_ok(Bool(a > b)),
+        (BinaryOp::Ge, Str(a), Str(b)) => res_ok(Bool(a >= b)),
+        (BinaryOp::Eq, Bool(a), Bool(b)) => res_ok(Bool(a == b)),
+        (BinaryOp::Ne, Bool(a), Bool(b)) => res_ok(Bool(a != b)),
+        (BinaryOp::Eq, Int(a), Int(b)) => res_ok(Bool(a == b)),
+        (BinaryOp::Ne, Int(a), Int(b)) => res_ok(Bool(a != b)),
+        (BinaryOp::Lt, Int(a), Int(b)) => res_ok(Bool(a < b)),
+        (BinaryOp::Le, Int(a), Int(b)) => res_ok(Bool(a <= b)),
+        (BinaryOp::Gt, Int(a), Int(b)) => res_ok(Bool(a > b)),
+        (BinaryOp::Ge, Int(a), Int(b)) => res_ok(Bool(a >= b)),
+        _ => match (lhs.as_float(), rhs.as_float()) {
+            (Some(a), Some(b)) => float_binary(op, a, b).ok_or_else(|| type_error(op, &lhs, &rhs).into()),
+            _ => res_err(type_error(op, &lhs, &rhs)),
+        }
+        .and_then(|value| match value {
+            Float(float) if !float.is_finite() => res_err(simple_error!("{} {} {} is not a finite number", lhs, op.symbol(), rhs)),
+            value => res_ok(value),
+        }),
+    }
+}
+
+fn float_binary(op: BinaryOp, a: f64, b: f64) -> Option<ExprValue> {
+    use ExprValue::*;
+    Some(match op {
+        BinaryOp::Add => Float(a + b),
+        BinaryOp::Sub => Float(a - b),
+        BinaryOp::Mul => Float(a * b),
+        BinaryOp::Div => Float(a / b),
+        BinaryOp::Rem => Float(a % b),
+        BinaryOp::Eq => Bool(a == b),
+        BinaryOp::Ne => Bool(a != b),
+        BinaryOp::Lt => Bool(a < b),
+        BinaryOp::Le => Bool(a <= b),
+        BinaryOp::Gt => Bool(a > b),
+        BinaryOp::Ge => Bool(a >= b),
+        BinaryOp::And | BinaryOp::Or => return None,
+    })
+}
+
+/// Precedence climbing over the source of one expression
+struct Parser<'s, 'f, F> {
+    src: &'s str,
+    pos: usize,
+    depth: usize,
+    intern: &'f mut F,
+}
+
+impl<'s, 'f, F> Parser<'s, 'f, F>
+where
+    F: FnMut(&'s str) -> MyResult<u8>,
+{
+    fn rest(&self) -> &'s str {
+        &self.src[self.pos..]
+    }
+    fn skip_space(&mut self) {
+        self.pos = self.src.len() - self.rest().trim_start().len();
+    }
+    /// Operands joined by operators binding at least as tight as `min_prec`
+    fn binary(&mut self, min_prec: u8) -> MyResult<Expr> {
+        let mut lhs = self.unary()?;
+        loop {
+            self.skip_space();
+            let Some(op) = BinaryOp::ALL.into_iter().find(|op| self.rest().starts_with(op.symbol())) else { break };
+            if op.precedence() < min_prec {
+                break;
+            }
+            self.pos += op.symbol().len();
+            let rhs = self.binary(op.precedence() + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        res_ok(lhs)
+    }
+    fn unary(&mut self) -> MyResult<Expr> {
+        self.skip_space();
+        let op = match self.rest().chars().next() {
+            Some('-') => UnaryOp::Neg,
+            Some('!') => UnaryOp::Not,
+            _ => return self.primary(),
+        };
+        self.pos += 1;
+        let operand = self.nested(Self::unary)?;
+        // negative literals stay literals
+        res_ok(match (op, operand) {
+            (UnaryOp::Neg, Expr::Int(int)) => Expr::Int(-int),
+            (UnaryOp::Neg, Expr::Float(float)) => Expr::Float(-float),
+            (op, operand) => Expr::Unary(op, Box::new(operand)),
+        })
+    }
+    fn nested(&mut self, parse: fn(&mut Self) -> MyResult<Expr>) -> MyResult<Expr> {
+        if self.depth >= MAX_DEPTH {
+            return res_err(simple_error!("Expression {:?} nests deeper than {} levels", self.src, MAX_DEPTH));
+        }
+        self.depth += 1;
+        let expr = parse(self);
+        self.depth -= 1;
+        expr
+    }
+    fn primary(&mut self) -> MyResult<Expr> {
+        let rest = self.rest();
+        let Some(first) = rest.chars().next() else {
+            return res_err(simple_error!("Expression {:?} ends where an operand was expected", self.src));
+        };
+        if first == '(' {
+            self.pos += 1;
+            let expr = self.nested(|parser| parser.binary(1))?;
+            self.skip_space();
+            if !self.rest().starts_with(')') {
+                return res_err(simple_error!("Expression {:?} is missing ')'", self.src));
+            }
+            self.pos += 1;
+            return res_ok(expr);
+        }
+        if first == '"' {
+            let end = closing_quote(rest)
+                .ok_or_else(|| simple_error!("Unterminated string in expression {:?}", self.src))?;
+            self.pos += end + 1;
+            return res_ok(Expr::Str(serde_json::from_str(&rest[..=end])?));
+        }
+        if first.is_ascii_digit() {
+            return self.number();
+        }
+        let len = rest.find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.')).unwrap_or(rest.len());
+        if len == 0 {
+            return res_err(simple_error!("Unexpected {:?} in expression {:?}", rest, self.src));
+        }
+        self.pos += len;
+        res_ok(match &rest[..len] {
+            "true" => Expr::Bool(true),
+            "false" => Expr::Bool(false),
+            name => Expr::Var((self.intern)(name)?),
+        })
+    }
+    /// `12`, `2.5` or `1e3`
+    fn number(&mut self) -> MyResult<Expr> {
+        let rest = self.rest();
+        let digits = |s: &str| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let mut len = digits(rest);
+        let mut is_float = false;
+        if rest[len..].starts_with('.') && digits(&rest[len + 1..]) > 0 {
+            len += 1 + digits(&rest[len + 1..]);
+            is_float = true;
+        }
+        if rest[len..].starts_with(['e', 'E']) {
+            let sign = usize::from(rest[len + 1..].starts_with(['+', '-']));
+            let exp = digits(&rest[len + 1 + sign..]);
+            if exp > 0 {
+                len += 1 + sign + exp;
+                is_float = true;
+            }
+        }
+        let literal = &rest[..len];
+        self.pos += len;
+        if is_float {
+            match literal.parse::<f64>() {
+                Ok(float) if float.is_finite() => res_ok(Expr::Float(float)),
+                _ => res_err(simple_error!("Number {} is out of range", literal)),
+            }
+        } else {
+            literal.parse()
+                .map(Expr::Int)
+                .map_err(|_| simple_error!("Integer {} is out of range", literal).into())
+        }
+    }
+}
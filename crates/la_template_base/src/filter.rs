@@ -0,0 +1,189 @@
+//! Filters that post-process a substituted value, e.g. `${name | escape("json")}`
+
+use std::{borrow::Cow, fmt};
+
+use common::{res_err, res_ok, MyResult};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use simple_error::simple_error;
+
+use crate::{
+    structured::{structured, to_json, to_yaml},
+    whitespace::{indent_at, indent_lines},
+    EscapeMode, FormatSpec, Layout,
+};
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub enum Filter {
+    /// `escape("json")`: overrides the template's default [EscapeMode]
+    Escape(EscapeMode),
+    /// `format(".2")`, or `${price:.2}` for short, see [FormatSpec]
+    Format(FormatSpec),
+    /// `indent`: prefixes every line of a multi-line value but the first
+    /// with this text, blank lines aside. Left empty, it is the output
+    /// before the value on its line, blanked to spaces, so that the lines
+    /// align with the first; `indent("  ")` sets it explicitly.
+    Indent(String),
+    /// `to_yaml`: writes a JSON array or object in YAML block style, see [Layout]
+    ToYaml(Layout),
+    /// `to_json`: writes a JSON value pretty-printed
+    ToJson(Layout),
+}
+
+impl Filter {
+    /// Whether the filter lays out multi-line values itself
+    pub(crate) fn indents(&self) -> bool {
+        matches!(self, Self::Indent(_) | Self::ToYaml(_) | Self::ToJson(_))
+    }
+    /// Indentation an enclosing include shifts. One taken from the output
+    /// already counts the shift.
+    pub(crate) fn indent_mut(&mut self) -> Option<&mut String> {
+        match self {
+            Self::Indent(indent) if indent.is_empty() => None,
+            Self::Indent(indent) | Self::ToYaml(Layout { indent, .. }) | Self::ToJson(Layout { indent, .. }) => Some(indent),
+            _ => None,
+        }
+    }
+    /// [Self::apply_at] the start of a line
+    pub fn apply<'a>(&self, value: Cow<'a, str>) -> MyResult<Cow<'a, str>> {
+        self.apply_at(value, b"")
+    }
+    /// Applies the filter to a value written after `output`, of which the
+    /// text after the last newline tells the column of an empty [Self::Indent]
+    /// and whether [Self::Escape] lands inside a quoted string
+    pub fn apply_at<'a>(&self, value: Cow<'a, str>, output: &[u8]) -> MyResult<Cow<'a, str>> {
+        match self {
+            Self::Escape(mode) => res_ok(mode.escape_at(value, output)),
+            Self::Format(spec) => spec.apply(&value).map(Cow::Owned),
+            Self::Indent(indent) if value.contains('\n') => {
+                let column;
+                let indent = match indent.is_empty() {
+                    true => {
+                        column = indent_at(output, output.len());
+                        &column
+                    }
+                    false => indent,
+                };
+                let indented = indent_lines(value.as_bytes(), indent, false);
+                res_ok(Cow::Owned(String::from_utf8(indented).expect("Lines split on ASCII newlines")))
+            }
+            Self::Indent(_) => res_ok(value),
+            Self::ToYaml(_) | Self::ToJson(_) => res_ok(Cow::Owned(self.write_structured(&structured(&value, None)))),
+        }
+    }
+    /// [Self::ToYaml] or [Self::ToJson] of `value`
+    fn write_structured(&self, value: &Value) -> String {
+        match self {
+            Self::ToYaml(layout) => to_yaml(value, layout),
+            Self::ToJson(layout) => to_json(value, layout),
+            _ => unreachable!("Only to_yaml and to_json write structured values"),
+        }
+    }
+    /// Parses a single filter invocation: `name` or `name(arg, "arg")`
+    pub fn parse(src: &str) -> MyResult<Self> {
+        let src = src.trim();
+        let (name, args) = match src.find('(') {
+            Some(open) => {
+                let args = src[open + 1..].strip_suffix(')')
+                    .ok_or_else(|| simple_error!("Filter {:?} is missing ')'", src))?;
+                (src[..open].trim(), parse_args(args)?)
+            }
+            None => (src, Vec::new()),
+        };
+        match (name, args.as_slice()) {
+            ("escape", [mode]) => res_ok(Self::Escape(mode.parse()?)),
+            ("escape", _) => res_err(simple_error!("escape expects exactly 1 argument, got {}", args.len())),
+            ("format", [spec]) => res_ok(Self::Format(FormatSpec::parse(spec)?)),
+            ("format", _) => res_err(simple_error!("format expects exactly 1 argument, got {}", args.len())),
+            ("indent", []) => res_ok(Self::Indent(String::new())),
+            ("indent", [indent]) => res_ok(Self::Indent(indent.clone())),
+            ("indent", _) => res_err(simple_error!("indent expects at most 1 argument, got {}", args.len())),
+            ("to_yaml", []) => res_ok(Self::ToYaml(Layout::default())),
+            ("to_json", []) => res_ok(Self::ToJson(Layout::default())),
+            ("to_yaml" | "to_json", _) => res_err(simple_error!("{} expects no arguments, got {}", name, args.len())),
+            _ => res_err(simple_error!("Unknown filter {:?}", name)),
+        }
+    }
+}
+
+/// Writes the filter back in template syntax
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Escape(mode) => write!(f, "escape(\"{}\")", mode),
+            Self::Format(spec) => write!(f, "format({})", serde_json::to_string(&spec.to_string()).map_err(|_| fmt::Error)?),
+            Self::Indent(indent) if indent.is_empty() => f.write_str("indent"),
+            Self::Indent(indent) => write!(f, "indent({})", serde_json::to_string(indent).map_err(|_| fmt::Error)?),
+            Self::ToYaml(_) => f.write_str("to_yaml"),
+            Self::ToJson(_) => f.write_str("to_json"),
+        }
+    }
+}
+
+/// Applies `filters` left to right
+pub fn apply_filters<'a>(filters: &[Filter], value: Cow<'a, str>) -> MyResult<Cow<'a, str>> {
+    apply_filters_at(filters, value, None, b"")
+}
+
+/// [apply_filters] to a value written after `output`, see [Filter::apply_at].
+/// `json` is the value `value` was read from, if any, which a leading
+/// `to_yaml` or `to_json` writes with its own types.
+pub fn apply_filters_at<'a>(filters: &[Filter], value: Cow<'a, str>, json: Option<&Value>, output: &[u8])
+    -> MyResult<Cow<'a, str>>
+{
+    let (value, filters) = match (json, filters) {
+        (Some(json), [first @ (Filter::ToYaml(_) | Filter::ToJson(_)), rest @ ..]) => {
+            (Cow::Owned(first.write_structured(&structured(&value, Some(json)))), rest)
+        }
+        _ => (value, filters),
+    };
+    filters.iter().try_fold(value, |value, filter| filter.apply_at(value, output))
+}
+
+/// Escaping left to apply after `filters`: none if they pick their own or
+/// write the value in the output's syntax
+pub fn escape_after(filters: &[Filter], default: Option<EscapeMode>) -> Option<EscapeMode> {
+    if filters.iter().any(|f| matches!(f, Filter::Escape(_) | Filter::ToYaml(_) | Filter::ToJson(_))) {
+        None
+    } else {
+        default
+    }
+}
+
+/// Splits `a, "b, c"` into `["a", "b, c"]`. Quoted arguments follow
+/// JSON string escaping.
+fn parse_args(src: &str) -> MyResult<Vec<String>> {
+    let mut args = Vec::new();
+    let mut rest = src.trim();
+    while !rest.is_empty() {
+        let (arg, remain) = if rest.starts_with('"') {
+            let end = closing_quote(rest)
+                .ok_or_else(|| simple_error!("Unterminated string argument in {:?}", src))?;
+            let arg: String = serde_json::from_str(&rest[..=end])?;
+            (arg, rest[end + 1..].trim_start())
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            (rest[..end].trim().to_string(), &rest[end..])
+        };
+        args.push(arg);
+        rest = match remain.strip_prefix(',') {
+            Some(after) => after.trim_start(),
+            None if remain.is_empty() => remain,
+            None => return res_err(simple_error!("Expected ',' between arguments in {:?}", src)),
+        };
+    }
+    res_ok(args)
+}
+
+/// Byte index of the quote closing the string `src` starts with
+pub(crate) fn closing_quote(src: &str) -> Option<usize> {
+    let mut escaped = false;
+    src.char_indices().skip(1).find_map(|(idx, c)| {
+        match (escaped, c) {
+            (false, '\\') => escaped = true,
+            (false, '"') => return Some(idx),
+            _ => escaped = false,
+        }
+        None
+    })
+}
@@ -0,0 +1,218 @@
+//! Format specifications for substituted values, e.g. `${price:.2}`,
+//! `${id:>8}` or `${flags:#06x}`:
+//!
+//! ```text
+//! [[fill]align][+][#][0][width][.precision][x|X|o|b]
+//! ```
+
+use std::fmt;
+
+use common::{res_err, res_ok, MyResult};
+use serde::{Deserialize, Serialize};
+use simple_error::simple_error;
+
+/// Largest width or precision a [FormatSpec] accepts, so that a typo such as
+/// `${x:999999999}` fails instead of allocating gigabytes
+pub const MAX_FORMAT_WIDTH: usize = 4096;
+
+use crate::ExprValue;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    /// `<`, the default for strings
+    Left,
+    /// `>`, the default for numbers
+    Right,
+    /// `^`
+    Center,
+}
+
+/// Integer bases, `#` adds their prefix
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Radix {
+    /// `x`
+    Hex,
+    /// `X`
+    UpperHex,
+    /// `o`
+    Octal,
+    /// `b`
+    Binary,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct FormatSpec {
+    pub fill: char,
+    pub align: Option<Align>,
+    /// `+`: sign non-negative numbers too
+    pub plus: bool,
+    /// `#`: prefix integers with `0x`, `0o` or `0b`
+    pub alternate: bool,
+    /// `0`: pad numbers with zeros after their sign and prefix
+    pub zero: bool,
+    pub width: Option<usize>,
+    /// Digits after the point for numbers, chars kept for strings
+    pub precision: Option<usize>,
+    pub radix: Option<Radix>,
+}
+
+impl Default for FormatSpec {
+    fn default() -> Self {
+        Self { fill: ' ', align: None, plus: false, alternate: false, zero: false, width: None, precision: None, radix: None }
+    }
+}
+
+fn align_of(c: char) -> Option<Align> {
+    match c {
+        '<' => Some(Align::Left),
+        '>' => Some(Align::Right),
+        '^' => Some(Align::Center),
+        _ => None,
+    }
+}
+
+impl FormatSpec {
+    pub fn parse(src: &str) -> MyResult<Self> {
+        let mut spec = Self::default();
+        let mut rest = src;
+        let mut chars = rest.chars();
+        match (chars.next(), chars.next().and_then(align_of)) {
+            (Some(fill), Some(align)) => {
+                spec.fill = fill;
+                spec.align = Some(align);
+                rest = &rest[fill.len_utf8() + 1..];
+            }
+            (Some(first), _) if align_of(first).is_some() => {
+                spec.align = align_of(first);
+                rest = &rest[1..];
+            }
+            _ => {}
+        }
+        let flag = |rest: &mut &str, c: char| match rest.strip_prefix(c) {
+            Some(after) => {
+                *rest = after;
+                true
+            }
+            None => false,
+        };
+        spec.plus = flag(&mut rest, '+');
+        spec.alternate = flag(&mut rest, '#');
+        spec.zero = flag(&mut rest, '0');
+        spec.width = number(&mut rest, src)?;
+        if flag(&mut rest, '.') {
+            spec.precision = Some(number(&mut rest, src)?
+                .ok_or_else(|| simple_error!("Format spec {:?} is missing the precision after '.'", src))?);
+        }
+        spec.radix = match rest {
+            "" => None,
+            "x" => Some(Radix::Hex),
+            "X" => Some(Radix::UpperHex),
+            "o" => Some(Radix::Octal),
+            "b" => Some(Radix::Binary),
+            _ => return res_err(simple_error!("Invalid format spec {:?}: unexpected {:?}", src, rest)),
+        };
+        res_ok(spec)
+    }
+    /// Formats `value`; numbers are recognized as in expressions, see [ExprValue::from_defn]
+    pub fn apply(&self, value: &str) -> MyResult<String> {
+        let parsed = ExprValue::from_defn(value);
+        let is_number = matches!(parsed, ExprValue::Int(_) | ExprValue::Float(_));
+        let body = match (self.radix, &parsed, self.precision) {
+            (Some(radix), ExprValue::Int(int), _) => {
+                let magnitude = int.unsigned_abs();
+                let (prefix, digits) = match radix {
+                    Radix::Hex => ("0x", format!("{:x}", magnitude)),
+                    Radix::UpperHex => ("0x", format!("{:X}", magnitude)),
+                    Radix::Octal => ("0o", format!("{:o}", magnitude)),
+                    Radix::Binary => ("0b", format!("{:b}", magnitude)),
+                };
+                let sign = if *int < 0 { "-" } else { "" };
+                format!("{}{}{}", sign, if self.alternate { prefix } else { "" }, digits)
+            }
+            (Some(_), value, _) => return res_err(simple_error!(
+                "Format spec {} expects an integer, got {:?}",
+                self,
+                value.to_string()
+            )),
+            (None, ExprValue::Int(int), Some(precision)) => format!("{:.*}", precision, *int as f64),
+            (None, ExprValue::Float(float), Some(precision)) => format!("{:.*}", precision, float),
+            (None, _, Some(precision)) => value.chars().take(precision).collect(),
+            (None, _, None) => value.to_string(),
+        };
+        let body = if self.plus && is_number && !body.starts_with('-') {
+            format!("+{}", body)
+        } else {
+            body
+        };
+        let len = body.chars().count();
+        let Some(pad) = self.width.and_then(|width| width.checked_sub(len)).filter(|pad| *pad > 0) else {
+            return res_ok(body);
+        };
+        if self.zero && is_number && self.align.is_none() {
+            // zeros go after the sign and the radix prefix
+            let sign = usize::from(body.starts_with(['+', '-']));
+            let digits_at = sign + if self.alternate && self.radix.is_some() { 2 } else { 0 };
+            return res_ok(format!("{}{}{}", &body[..digits_at], "0".repeat(pad), &body[digits_at..]));
+        }
+        let fill = |n: usize| self.fill.to_string().repeat(n);
+        let align = self.align.unwrap_or(if is_number { Align::Right } else { Align::Left });
+        res_ok(match align {
+            Align::Left => format!("{}{}", body, fill(pad)),
+            Align::Right => format!("{}{}", fill(pad), body),
+            Align::Center => format!("{}{}{}", fill(pad / 2), body, fill(pad - pad / 2)),
+        })
+    }
+}
+
+/// Digits at the start of `rest`, consumed
+fn number(rest: &mut &str, src: &str) -> MyResult<Option<usize>> {
+    let len = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+    if len == 0 {
+        return res_ok(None);
+    }
+    let (digits, after) = rest.split_at(len);
+    *rest = after;
+    match digits.parse() {
+        Ok(number) if number <= MAX_FORMAT_WIDTH => res_ok(Some(number)),
+        _ => res_err(simple_error!(
+            "Format spec {:?} has a number out of range: {}, the maximum is {}",
+            src,
+            digits,
+            MAX_FORMAT_WIDTH
+        )),
+    }
+}
+
+/// Writes the spec back, without the leading `:`
+impl fmt::Display for FormatSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(align) = self.align {
+            if self.fill != ' ' {
+                write!(f, "{}", self.fill)?;
+            }
+            f.write_str(match align {
+                Align::Left => "<",
+                Align::Right => ">",
+                Align::Center => "^",
+            })?;
+        }
+        for (set, flag) in [(self.plus, "+"), (self.alternate, "#"), (self.zero, "0")] {
+            if set {
+                f.write_str(flag)?;
+            }
+        }
+        if let Some(width) = self.width {
+            write!(f, "{}", width)?;
+        }
+        if let Some(precision) = self.precision {
+            write!(f, ".{}", precision)?;
+        }
+        f.write_str(match self.radix {
+            None => "",
+            Some(Radix::Hex) => "x",
+            Some(Radix::UpperHex) => "X",
+            Some(Radix::Octal) => "o",
+            Some(Radix::Binary) => "b",
+        })
+    }
+}
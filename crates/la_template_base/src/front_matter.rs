@@ -0,0 +1,67 @@
+//! Per-template settings declared at the top of a template:
+//!
+//! ```text
+//! +++
+//! escape = "json"
+//! +++
+//! {"name": "${name}"}
+//! ```
+
+use std::fmt;
+
+use common::{res_err, res_ok, MyResult};
+use serde::{Deserialize, Serialize};
+use simple_error::simple_error;
+
+use crate::EscapeMode;
+
+/// Line that opens and closes the front matter
+pub const FRONT_MATTER_FENCE: &str = "+++";
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct FrontMatter {
+    /// Escaping applied to substitutions that do not pick their own
+    pub escape: Option<EscapeMode>,
+    /// Gives every substitution and include the `indent` filter
+    pub indent: bool,
+}
+
+impl FrontMatter {
+    /// Parses the `key = "value"` lines found between the fences.
+    /// Blank lines and lines starting with `#` are ignored.
+    pub fn parse(src: &str) -> MyResult<Self> {
+        let mut front_matter = Self::default();
+        for line in src.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+            let (key, value) = line.split_once('=')
+                .ok_or_else(|| simple_error!("Expected `key = value` in front matter, got {:?}", line))?;
+            let value = value.trim();
+            let value = value.strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value);
+            match key.trim() {
+                "escape" => front_matter.escape = Some(value.parse()?),
+                "indent" => front_matter.indent = value.parse()
+                    .map_err(|_| simple_error!("Expected `indent = true` or `false`, got {:?}", value))?,
+                key => return res_err(simple_error!("Unknown front matter key {:?}", key)),
+            }
+        }
+        res_ok(front_matter)
+    }
+}
+
+/// Writes the fenced front matter back, or nothing if every setting is default
+impl fmt::Display for FrontMatter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self == &Self::default() {
+            return Ok(());
+        }
+        writeln!(f, "{}", FRONT_MATTER_FENCE)?;
+        if let Some(escape) = self.escape {
+            writeln!(f, "escape = \"{}\"", escape)?;
+        }
+        if self.indent {
+            writeln!(f, "indent = true")?;
+        }
+        writeln!(f, "{}", FRONT_MATTER_FENCE)
+    }
+}
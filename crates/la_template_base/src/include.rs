@@ -0,0 +1,355 @@
+//! Partials: `${% include "header.t.yml" %}` inlines another template, which
+//! sees the variables of the including one, plus those bound with `with`:
+//!
+//! ```text
+//! ${% include "@partials/task.t.yml" with name = "Restart " + service, retries = 3 %}
+//! ```
+//!
+//! With `indent` after the path, or `indent = true` in the front matter, the
+//! included lines are shifted to the column the tag starts at.
+//!
+//! [ConcreteTemplate::resolve_includes] inlines them before rendering.
+
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+};
+
+use cf_fs::{PathPluggable, PathRemap};
+use common::{res_err, res_ok, MyResult};
+use itertools::Itertools;
+use simple_error::simple_error;
+
+use crate::{
+    closing_quote, intern, is_var_name, macros::rebind_call, push_str, split_unquoted, whitespace::indent_lines, ConcreteTemplate, Expr,
+    Expression, Filter, Substitution, Tag, TemplateTrait, Token,
+};
+
+/// Nesting limit for `include`
+const MAX_INCLUDE_DEPTH: usize = 64;
+
+/// Where `include` looks for templates. Names starting with `@` go through
+/// the aliases, e.g. `@partials/header.t`, and absolute ones are taken as
+/// is. Others are searched next to the including template, then in each
+/// directory in turn.
+#[derive(Debug, Clone, Default)]
+pub struct IncludePath {
+    dirs: Vec<PathBuf>,
+    remap: Option<PathRemap>,
+}
+
+impl IncludePath {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Searches `dir` after the directories given before
+    pub fn with_dir<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.dirs.push(dir.as_ref().to_path_buf());
+        self
+    }
+    /// `@alias/...` names, e.g. `("partials", "templates/partials")`
+    pub fn with_aliases<I, K, V>(mut self, aliases: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let map = aliases.into_iter()
+            .map(|(alias, dir)| (alias.as_ref().as_bytes().to_vec(), dir.as_ref().as_bytes().to_vec()))
+            .collect();
+        self.remap = Some(PathRemap::new(map));
+        self
+    }
+    /// The first of the paths `name` may refer to from the last template of
+    /// `chain` that `load` reads, `kind` being `include` or `import`
+    pub(crate) fn load<F>(&self, kind: &str, name: &str, chain: &[PathBuf], load: &mut F) -> MyResult<(PathBuf, ConcreteTemplate)>
+    where
+        F: FnMut(&Path) -> MyResult<ConcreteTemplate>,
+    {
+        let includer = chain.last().expect("The chain starts with the template being resolved");
+        let resolved_chain = chain.iter().map(|path| resolved_path(path)).collect::<Vec<_>>();
+        let mut tried = Vec::new();
+        let mut cycle = None;
+        for path in self.candidates(name, includer)? {
+            // a template being resolved is skipped, e.g. for a partial of
+            // the same name in a search directory
+            if resolved_chain.contains(&resolved_path(&path)) {
+                cycle.get_or_insert(path);
+                continue;
+            }
+            match load(&path) {
+                Ok(template) => return res_ok((path, template)),
+                Err(e) => tried.push(format!("{}: {}", path.display(), e)),
+            }
+        }
+        if let Some(path) = cycle {
+            return res_err(simple_error!(
+                "{}{} cycle: {} -> {}",
+                kind[..1].to_uppercase(),
+                &kind[1..],
+                chain.iter().map(|p| p.display()).join(" -> "),
+                path.display()
+            ));
+        }
+        res_err(simple_error!("Cannot {} {:?} from {}, tried:\n{}", kind, name, includer.display(), tried.join("\n")))
+    }
+    /// Paths `name` may refer to from `includer`, in order
+    fn candidates(&self, name: &str, includer: &Path) -> MyResult<Vec<PathBuf>> {
+        if name.starts_with('@') {
+            let remap = self.remap.as_ref()
+                .ok_or_else(|| simple_error!("No alias is defined for include {:?}", name))?;
+            return res_ok(vec![PathBuf::from(remap.output(name)?.as_ref())]);
+        }
+        if Path::new(name).is_absolute() {
+            return res_ok(vec![PathBuf::from(name)]);
+        }
+        let here = includer.parent().unwrap_or(Path::new(""));
+        res_ok(std::iter::once(here)
+            .chain(self.dirs.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(name))
+            .collect())
+    }
+}
+
+/// What `path` refers to, for telling whether two paths name the same
+/// template: lexically normalized, without `.` and with `..` applied, as
+/// templates need not come from the disk
+pub(crate) fn resolved_path(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if matches!(normal.components().next_back(), Some(Component::Normal(_))) => {
+                normal.pop();
+            }
+            component => normal.push(component),
+        }
+    }
+    normal
+}
+
+impl ConcreteTemplate {
+    /// Inlines `include` tags, recursively. `path` is where this template
+    /// was read from; included templates are found through `search` and
+    /// read with `load`, and may extend others.
+    ///
+    /// ```
+    /// # use la_template_base::*;
+    /// # use std::{io::Cursor, path::Path};
+    /// let page = parse_template(Cursor::new("${% include \"hi.t.txt\" with who = name %}!")).unwrap();
+    /// let resolved = page.resolve_includes(Path::new("page.t.txt"), &IncludePath::new(), |_| {
+    ///     parse_template(Cursor::new("hello ${who}"))
+    /// }).unwrap();
+    /// assert_eq!(resolved.to_source().unwrap(), "hello ${name}!");
+    /// ```
+    pub fn resolve_includes<F>(self, path: &Path, search: &IncludePath, mut load: F) -> MyResult<Self>
+    where
+        F: FnMut(&Path) -> MyResult<ConcreteTemplate>,
+    {
+        if !self.tokens().iter().any(|tok| matches!(tok, Token::Tag(tag) if tag.name == "include")) {
+            return res_ok(self);
+        }
+        let mut inliner = Inliner {
+            search,
+            load: &mut load,
+            chain: vec![path.to_path_buf()],
+            symbols: Vec::new(),
+            tokens: Vec::new(),
+        };
+        inliner.inline(&self, &HashMap::new())?;
+        res_ok(ConcreteTemplate {
+            front_matter: self.front_matter,
+            tokens: inliner.tokens,
+            symbols: inliner.symbols,
+        })
+    }
+}
+
+/// The string literal `args` starts with, and what follows it, trimmed
+pub(crate) fn quoted_arg<'a>(args: &'a str, tag: &str) -> MyResult<(String, &'a str)> {
+    match closing_quote(args) {
+        Some(end) if args.starts_with('"') => res_ok((serde_json::from_str(&args[..=end])?, args[end + 1..].trim())),
+        _ => res_err(simple_error!("Expected a quoted path after {}, got {:?}", tag, args)),
+    }
+}
+
+/// `"path" indent with a = expr, b = expr`
+struct IncludeArgs<'a> {
+    path: String,
+    indent: bool,
+    /// Variable names and the source of their expressions
+    bindings: Vec<(&'a str, &'a str)>,
+}
+
+fn parse_include(args: &str) -> MyResult<IncludeArgs<'_>> {
+    let (path, rest) = quoted_arg(args, "include")?;
+    let (indent, rest) = match rest.strip_prefix("indent") {
+        Some(after) if after.is_empty() || after.starts_with(char::is_whitespace) => (true, after.trim_start()),
+        _ => (false, rest),
+    };
+    if rest.is_empty() {
+        return res_ok(IncludeArgs { path, indent, bindings: Vec::new() });
+    }
+    let bindings = rest.strip_prefix("with")
+        .filter(|rest| rest.starts_with(char::is_whitespace))
+        .ok_or_else(|| simple_error!("Expected `with name = value, ...` after the path of include, got {:?}", rest))?;
+    let bindings = split_unquoted(bindings, b',')
+        .into_iter()
+        .map(|binding| match binding.split_once('=') {
+            Some((var, expr)) if is_var_name(var.trim()) => res_ok((var.trim(), expr.trim())),
+            _ => res_err(simple_error!("Expected `name = value` in include, got {:?}", binding.trim())),
+        })
+        .collect::<MyResult<_>>()?;
+    res_ok(IncludeArgs { path, indent, bindings })
+}
+
+struct Inliner<'s, F> {
+    search: &'s IncludePath,
+    load: &'s mut F,
+    /// The templates being inlined, outermost first
+    chain: Vec<PathBuf>,
+    symbols: Vec<String>,
+    tokens: Vec<Token>,
+}
+
+impl<'s, F> Inliner<'s, F>
+where
+    F: FnMut(&Path) -> MyResult<ConcreteTemplate>,
+{
+    /// Appends the tokens of `template`, its variables replaced by their
+    /// `bindings`, which are expressions over the output's variables
+    fn inline(&mut self, template: &ConcreteTemplate, bindings: &HashMap<String, Expr>) -> MyResult<()> {
+        for tok in template.tokens() {
+            match tok {
+                Token::Str(s) => push_str(&mut self.tokens, s.clone()),
+                Token::Tag(tag) if tag.name == "include" => self.include(tag, template.front_matter().indent, bindings)?,
+                tok => {
+                    let tok = rebind(tok, template.symbols(), bindings, &mut self.symbols)?;
+                    self.tokens.push(Tag::from_file(&tok, self.chain.last().unwrap()));
+                }
+            }
+        }
+        res_ok(())
+    }
+    fn include(&mut self, tag: &Tag, indent_all: bool, bindings: &HashMap<String, Expr>) -> MyResult<()> {
+        let IncludeArgs { path: name, indent, bindings: with } = parse_include(&tag.args)?;
+        let mut inner = HashMap::new();
+        for (var, src) in with {
+            // names of the including template, bound in turn by its includer
+            inner.insert(var.to_string(), bind_expr(src, bindings, &mut self.symbols)?);
+        }
+        let (path, template) = self.search.load("include", &name, &self.chain, self.load)?;
+        if self.chain.len() > MAX_INCLUDE_DEPTH {
+            return res_err(simple_error!("Includes nest more than {} levels deep", MAX_INCLUDE_DEPTH));
+        }
+        let template = template.resolve_inheritance(&path, &mut *self.load)?;
+        let outer = std::mem::take(&mut self.tokens);
+        self.chain.push(path);
+        self.inline(&template, &inner)?;
+        self.chain.pop();
+        let included = std::mem::replace(&mut self.tokens, outer);
+        splice(&mut self.tokens, included, (indent || indent_all).then_some(&tag.indent));
+        res_ok(())
+    }
+}
+
+/// The variable `name` of an inlined template, over the output's `symbols`
+fn resolve_var(name: &str, bindings: &HashMap<String, Expr>, symbols: &mut Vec<String>) -> MyResult<Expr> {
+    match bindings.get(name) {
+        Some(expr) => res_ok(expr.clone()),
+        None => intern(symbols, name).map(Expr::Var),
+    }
+}
+
+/// The expression `src` over the variables of an inlined template, with
+/// these replaced by their `bindings` or else those of the output
+pub(crate) fn bind_expr(src: &str, bindings: &HashMap<String, Expr>, symbols: &mut Vec<String>) -> MyResult<Expr> {
+    let mut names = Vec::new();
+    let expr = Expr::parse(src, &mut |name| intern(&mut names, name))?;
+    let vars = names.iter()
+        .map(|name| resolve_var(name, bindings, symbols))
+        .collect::<MyResult<Vec<_>>>()?;
+    res_ok(expr.replace_vars(&mut |var| vars[var as usize].clone()))
+}
+
+/// `tok` of an inlined template whose variables are `names`, these
+/// replaced by their `bindings` or else those of the output's `symbols`
+pub(crate) fn rebind(
+    tok: &Token,
+    names: &[String],
+    bindings: &HashMap<String, Expr>,
+    symbols: &mut Vec<String>,
+) -> MyResult<Token> {
+    res_ok(match tok {
+        Token::Var(var) => match resolve_var(&names[*var as usize], bindings, symbols)? {
+            Expr::Var(var) => Token::Var(var),
+            expr => Token::Expr(Expression { expr, filters: Vec::new() }),
+        },
+        Token::Subst(subst) => {
+            let filters = subst.filters.clone();
+            match resolve_var(&names[subst.var as usize], bindings, symbols)? {
+                Expr::Var(var) => Token::Subst(Substitution { var, filters }),
+                expr => Token::Expr(Expression { expr, filters }),
+            }
+        }
+        Token::Expr(expression) => {
+            let vars = expression.expr.vars()
+                .into_iter()
+                .map(|var| res_ok((var, resolve_var(&names[var as usize], bindings, symbols)?)))
+                .collect::<MyResult<HashMap<_, _>>>()?;
+            Token::Expr(Expression {
+                expr: expression.expr.replace_vars(&mut |var| vars[&var].clone()),
+                filters: expression.filters.clone(),
+            })
+        }
+        // arguments of calls, expanded after includes are inlined
+        Token::Tag(tag) if tag.name == "call" && !bindings.is_empty() => Token::Tag(rebind_call(tag, bindings, symbols)?),
+        tok => tok.clone(),
+    })
+}
+
+/// Appends `inserted` to `tokens`, shifted right by `indent` if given
+pub(crate) fn splice(tokens: &mut Vec<Token>, mut inserted: Vec<Token>, indent: Option<&String>) {
+    if let Some(indent) = indent.filter(|indent| !indent.is_empty()) {
+        // a standalone tag took the indentation of its line away
+        let at_line_start = match tokens.last() {
+            None => true,
+            Some(tok) => tok.literal_bytes().ends_with(b"\n"),
+        };
+        indent_tokens(&mut inserted, indent, at_line_start);
+    }
+    for tok in inserted {
+        match tok {
+            Token::Str(s) => push_str(tokens, s),
+            tok => tokens.push(tok),
+        }
+    }
+}
+
+/// Shifts inserted `tokens` right by `indent`, their first line too if it
+/// starts a line of the output
+fn indent_tokens(tokens: &mut Vec<Token>, indent: &str, at_line_start: bool) {
+    if at_line_start {
+        match tokens.first_mut() {
+            Some(Token::Str(s)) if s.starts_with('\n') => {}
+            Some(Token::Str(s)) => s.insert_str(0, indent),
+            Some(_) => tokens.insert(0, Token::Str(indent.to_string())),
+            None => {}
+        }
+    }
+    let len = tokens.len();
+    for (idx, tok) in tokens.iter_mut().enumerate() {
+        let follows = idx + 1 < len;
+        match tok {
+            Token::Str(s) => *s = String::from_utf8(indent_lines(s.as_bytes(), indent, follows))
+                .expect("Lines split on ASCII newlines"),
+            Token::Bytes(bytes) => *bytes = indent_lines(bytes, indent, follows),
+            Token::Subst(Substitution { filters, .. }) | Token::Expr(Expression { filters, .. }) => {
+                for inner in filters.iter_mut().filter_map(Filter::indent_mut) {
+                    inner.insert_str(0, indent);
+                }
+            }
+            _ => {}
+        }
+    }
+}
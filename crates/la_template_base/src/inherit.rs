@@ -0,0 +1,274 @@
+//! Template inheritance: a child template names its parent and overrides
+//! some of the parent's blocks, the rest of the parent is kept as is.
+//!
+//! ```text
+//! ${% extends "base.t.yml" %}
+//! ${% block tasks %}
+//! ${% super %}
+//! - name: Restart ${service}
+//! ${% endblock %}
+//! ```
+//!
+//! [ConcreteTemplate::resolve_inheritance] flattens the chain of templates
+//! into one that renders like any other.
+
+use std::{collections::HashMap, path::Path};
+
+use common::{res_err, res_ok, MyResult};
+use simple_error::simple_error;
+
+use crate::{
+    intern, is_var_name, push_str, quoted_arg, resolved_path, ConcreteTemplate, Expression, FrontMatter, Substitution,
+    Tag, TemplateTrait, Token,
+};
+
+/// Nesting limit for `extends`
+const MAX_EXTENDS_DEPTH: usize = 32;
+
+/// Tags this module resolves, all others are kept
+const INHERITANCE_TAGS: [&str; 4] = ["extends", "block", "endblock", "super"];
+
+#[derive(Debug)]
+enum Node<'t> {
+    Token(&'t Token),
+    /// Placeholder for the block of that name, see [Level::blocks]
+    Block(&'t str),
+    Super,
+}
+
+/// One template of the chain, its tokens grouped into blocks
+struct Level<'t> {
+    template: &'t ConcreteTemplate,
+    /// Where `template` was read from
+    path: &'t Path,
+    nodes: Vec<Node<'t>>,
+    /// The body of every block, nested ones included
+    blocks: HashMap<&'t str, Vec<Node<'t>>>,
+}
+
+impl ConcreteTemplate {
+    /// Resolves `extends`, `block` and `super` tags into a template without
+    /// them. `path` is where this template was read from; parents are named
+    /// relative to their child's directory and read with `load`.
+    ///
+    /// ```
+    /// # use la_template_base::*;
+    /// # use std::{io::Cursor, path::Path};
+    /// let child = parse_template(Cursor::new("${% extends \"base.t.txt\" %}\n${% block who %}${name}${% endblock %}")).unwrap();
+    /// let resolved = child.resolve_inheritance(Path::new("child.t.txt"), |_| {
+    ///     parse_template(Cursor::new("hello ${% block who %}world${% endblock %}!"))
+    /// }).unwrap();
+    /// assert_eq!(resolved.to_source().unwrap(), "hello ${name}!");
+    /// ```
+    pub fn resolve_inheritance<F>(self, path: &Path, mut load: F) -> MyResult<Self>
+    where
+        F: FnMut(&Path) -> MyResult<ConcreteTemplate>,
+    {
+        if !self.tokens().iter().any(is_inheritance_tag) {
+            return res_ok(self);
+        }
+        let mut paths = vec![path.to_path_buf()];
+        let mut templates = vec![self];
+        while let Some(parent) = extends_of(templates.last().unwrap())? {
+            let child = paths.last().unwrap();
+            let parent = child.parent().unwrap_or(Path::new("")).join(parent);
+            if paths.iter().any(|path| resolved_path(path) == resolved_path(&parent)) {
+                return res_err(simple_error!("{} extends itself through {}", parent.display(), child.display()));
+            }
+            if paths.len() > MAX_EXTENDS_DEPTH {
+                return res_err(simple_error!("Templates extend each other more than {} levels deep", MAX_EXTENDS_DEPTH));
+            }
+            let template = load(&parent)
+                .map_err(|e| simple_error!("Failed to load {}, extended by {}: {}", parent.display(), child.display(), e))?;
+            paths.push(parent);
+            templates.push(template);
+        }
+        let levels = templates.iter()
+            .zip(&paths)
+            .map(|(template, path)| Level::parse(template, path))
+            .collect::<MyResult<Vec<_>>>()?;
+        for (idx, level) in levels.iter().enumerate().rev().skip(1) {
+            check_overrides(level, &levels[idx + 1..], &paths[idx])?;
+        }
+        let root = levels.len() - 1;
+        let mut resolver = Resolver { levels: &levels, symbols: Vec::new(), tokens: Vec::new(), active: Vec::new() };
+        resolver.emit(root, &levels[root].nodes, None)?;
+        res_ok(ConcreteTemplate {
+            // the closest settings win
+            front_matter: templates.iter()
+                .map(TemplateTrait::front_matter)
+                .find(|front_matter| **front_matter != FrontMatter::default())
+                .cloned()
+                .unwrap_or_default(),
+            tokens: resolver.tokens,
+            symbols: resolver.symbols,
+        })
+    }
+}
+
+fn is_inheritance_tag(tok: &Token) -> bool {
+    matches!(tok, Token::Tag(tag) if INHERITANCE_TAGS.contains(&tag.name.as_str()))
+}
+
+/// Path named by the `extends` tag of `template`, if any
+fn extends_of(template: &ConcreteTemplate) -> MyResult<Option<String>> {
+    let Some(tag) = template.tokens().iter().find_map(|tok| match tok {
+        Token::Tag(tag) if tag.name == "extends" => Some(tag),
+        _ => None,
+    }) else {
+        return res_ok(None);
+    };
+    match quoted_arg(&tag.args, "extends")? {
+        (path, "") => res_ok(Some(path)),
+        (_, rest) => res_err(simple_error!("Unexpected {:?} after the path of extends", rest)),
+    }
+}
+
+impl<'t> Level<'t> {
+    fn parse(template: &'t ConcreteTemplate, path: &'t Path) -> MyResult<Self> {
+        let mut extends = false;
+        let mut blocks = HashMap::new();
+        // the block being read last, the top level first
+        let mut stack: Vec<(&str, Vec<Node>)> = vec![("", Vec::new())];
+        for tok in template.tokens() {
+            let Token::Tag(tag) = tok else {
+                stack.last_mut().unwrap().1.push(Node::Token(tok));
+                continue;
+            };
+            match tag.name.as_str() {
+                "extends" if extends || stack.len() > 1 || !stack[0].1.iter().all(is_blank) => {
+                    return res_err(simple_error!("extends must come first in {}, and only once", path.display()));
+                }
+                "extends" => extends = true,
+                "block" if !is_var_name(&tag.args) || tag.args.contains('.') => {
+                    return res_err(simple_error!("Invalid block name {:?} in {}", tag.args, path.display()));
+                }
+                "block" => stack.push((tag.args.as_str(), Vec::new())),
+                "endblock" => {
+                    let (name, body) = match stack.pop() {
+                        Some((name, body)) if !name.is_empty() => (name, body),
+                        _ => return res_err(simple_error!("endblock without a block in {}", path.display())),
+                    };
+                    if !tag.args.is_empty() && tag.args != name {
+                        return res_err(simple_error!("endblock {} closes block {} in {}", tag.args, name, path.display()));
+                    }
+                    if blocks.insert(name, body).is_some() {
+                        return res_err(simple_error!("Block {} is defined twice in {}", name, path.display()));
+                    }
+                    stack.last_mut().unwrap().1.push(Node::Block(name));
+                }
+                "super" if stack.len() == 1 => {
+                    return res_err(simple_error!("super outside of a block in {}", path.display()));
+                }
+                "super" => stack.last_mut().unwrap().1.push(Node::Super),
+                _ => stack.last_mut().unwrap().1.push(Node::Token(tok)),
+            }
+        }
+        let (name, nodes) = stack.pop().unwrap();
+        if !name.is_empty() {
+            return res_err(simple_error!("Block {} is missing its endblock in {}", name, path.display()));
+        }
+        if extends && !nodes.iter().all(|node| is_blank(node) || matches!(node, Node::Block(_))) {
+            return res_err(simple_error!(
+                "{} extends another template, so everything but whitespace must be inside blocks",
+                path.display()
+            ));
+        }
+        res_ok(Self { template, path, nodes, blocks })
+    }
+}
+
+fn is_blank(node: &Node) -> bool {
+    matches!(node, Node::Token(Token::Str(s)) if s.trim().is_empty())
+}
+
+/// Top-level blocks of a child must override one of its ancestors', or
+/// they would never show; nested ones may be new
+fn check_overrides(level: &Level, ancestors: &[Level], path: &Path) -> MyResult<()> {
+    for node in &level.nodes {
+        match node {
+            Node::Block(name) if !ancestors.iter().any(|a| a.blocks.contains_key(name)) => {
+                return res_err(simple_error!("Block {} of {} is not defined by any template it extends", name, path.display()));
+            }
+            _ => {}
+        }
+    }
+    res_ok(())
+}
+
+struct Resolver<'l, 't> {
+    /// The child first, the root last
+    levels: &'l [Level<'t>],
+    symbols: Vec<String>,
+    tokens: Vec<Token>,
+    /// Names of the blocks being emitted, outermost first
+    active: Vec<&'t str>,
+}
+
+impl<'l, 't> Resolver<'l, 't> {
+    /// Emits `nodes` of the `level`-th template, inside `block` if any
+    fn emit(&mut self, level: usize, nodes: &[Node<'t>], block: Option<&'t str>) -> MyResult<()> {
+        for node in nodes {
+            match node {
+                Node::Token(tok) => self.push(level, tok)?,
+                Node::Block(name) => {
+                    // the most derived definition wins
+                    let (at, body) = self.find(name, 0).unwrap();
+                    let name: &'t str = name;
+                    self.emit_block(name, at, body)?;
+                }
+                Node::Super => {
+                    let name = block.unwrap();
+                    let (at, body) = self.find(name, level + 1)
+                        .ok_or_else(|| simple_error!("super in block {}, which no parent template defines", name))?;
+                    self.emit(at, body, Some(name))?;
+                }
+            }
+        }
+        res_ok(())
+    }
+    fn emit_block(&mut self, name: &'t str, level: usize, body: &[Node<'t>]) -> MyResult<()> {
+        if self.active.contains(&name) {
+            return res_err(simple_error!("Block {} contains itself", name));
+        }
+        self.active.push(name);
+        self.emit(level, body, Some(name))?;
+        self.active.pop();
+        res_ok(())
+    }
+    /// First definition of the block `name` from the `from`-th template on
+    fn find(&self, name: &str, from: usize) -> Option<(usize, &'l [Node<'t>])> {
+        self.levels.iter()
+            .enumerate()
+            .skip(from)
+            .find_map(|(idx, level)| level.blocks.get(name).map(|body| (idx, body.as_slice())))
+    }
+    /// Copies `tok` of the `level`-th template, renumbering its variables
+    fn push(&mut self, level: usize, tok: &Token) -> MyResult<()> {
+        let levels = self.levels;
+        let symbols = levels[level].template.symbols();
+        match tok {
+            Token::Str(s) => push_str(&mut self.tokens, s.clone()),
+            Token::Var(var) => {
+                let var = intern(&mut self.symbols, &symbols[*var as usize])?;
+                self.tokens.push(Token::Var(var));
+            }
+            Token::Subst(subst) => {
+                let var = intern(&mut self.symbols, &symbols[subst.var as usize])?;
+                self.tokens.push(Token::Subst(Substitution { var, filters: subst.filters.clone() }));
+            }
+            Token::Expr(expression) => {
+                let vars = expression.expr.vars()
+                    .into_iter()
+                    .map(|var| res_ok((var, intern(&mut self.symbols, &symbols[var as usize])?)))
+                    .collect::<MyResult<HashMap<_, _>>>()?;
+                self.tokens.push(Token::Expr(Expression {
+                    expr: expression.expr.map_vars(&mut |var| vars[&var]),
+                    filters: expression.filters.clone(),
+                }));
+            }
+            tok => self.tokens.push(Tag::from_file(tok, levels[level].path)),
+        }
+        res_ok(())
+    }
+}
@@ -0,0 +1,111 @@
+//! Variables computed by callbacks, e.g. a secret fetched from a vault or a
+//! checksum, evaluated only when a template references them
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt,
+    sync::{atomic::{AtomicU64, Ordering}, Mutex},
+};
+
+use common::{res_ok, MyResult};
+use simple_error::simple_error;
+
+use crate::{VariableMap, VariableTrait};
+
+type Compute = Box<dyn Fn() -> MyResult<String> + Send + Sync>;
+
+struct LazyVar {
+    compute: Compute,
+    /// The value and the render it was computed for
+    value: Mutex<Option<(u64, String)>>,
+}
+
+/// Variables backed by closures. A closure runs on the first lookup of its
+/// variable in a render and its result is cached for the rest of that
+/// render, so that each value is computed at most once per render and
+/// afresh for the next; failures are not cached.
+///
+/// ```
+/// # use la_template_base::*;
+/// # use std::io::Cursor;
+/// let vars = LazyVariables::new()
+///     .define("user", || Ok("naomi".to_string()))
+///     .define("token", || Err("vault unreachable".into()));
+/// assert_eq!(generate_template(Cursor::new("hi ${user}"), vars).unwrap(), "hi naomi");
+/// ```
+#[derive(Default)]
+pub struct LazyVariables {
+    vars: HashMap<String, LazyVar>,
+    /// Counts renders, see [VariableMap::begin_render]
+    render: AtomicU64,
+}
+
+impl LazyVariables {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Computes `name` with `compute`, replacing any previous definition.
+    /// Closures are `Send + Sync`, so that the variables can be shared across threads.
+    pub fn define<N, F>(mut self, name: N, compute: F) -> Self
+    where
+        N: Into<String>,
+        F: Fn() -> MyResult<String> + Send + Sync + 'static,
+    {
+        self.vars.insert(name.into(), LazyVar { compute: Box::new(compute), value: Mutex::new(None) });
+        self
+    }
+    /// Forgets the cached values, so that the next render computes them afresh
+    pub fn clear_cache(&mut self) {
+        self.vars.values_mut().for_each(|var| drop(var.value.get_mut().unwrap_or_else(|e| e.into_inner()).take()));
+    }
+    /// Whether `name` was computed already in the current render
+    pub fn is_cached(&self, name: &str) -> bool {
+        self.cached(name).is_some()
+    }
+    fn cached(&self, name: &str) -> Option<String> {
+        match &*self.vars.get(name)?.value.lock().unwrap_or_else(|e| e.into_inner()) {
+            Some((render, value)) if *render == self.render.load(Ordering::Relaxed) => Some(value.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl VariableMap {
+    /// Starts a render: values cached by [LazyVariables] for earlier
+    /// renders are computed again
+    pub(crate) fn begin_render(&self) {
+        if let Self::Lazy(lazy) = self {
+            lazy.render.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl VariableTrait for LazyVariables {
+    fn _get_defn<'a>(&'a self, key: &str) -> MyResult<Cow<'a, str>> {
+        let var = self.vars.get(key)
+            .ok_or_else(|| simple_error!("Var {} expected, but not defined", key))?;
+        if let Some(value) = self.cached(key) {
+            return res_ok(value.into());
+        }
+        let value = (var.compute)()
+            .map_err(|e| simple_error!("Failed to compute {}: {}", key, e))?;
+        *var.value.lock().unwrap_or_else(|e| e.into_inner()) = Some((self.render.load(Ordering::Relaxed), value.clone()));
+        res_ok(value.into())
+    }
+    /// Defined names are known without running their closures
+    fn defines(&self, key: &str) -> bool {
+        self.vars.contains_key(key)
+    }
+    fn names(&self) -> Option<Vec<&str>> {
+        Some(self.vars.keys().map(String::as_str).collect())
+    }
+}
+
+impl fmt::Debug for LazyVariables {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.vars.keys().map(|name| (name, self.cached(name))))
+            .finish()
+    }
+}
@@ -1,5 +1,24 @@
 // mod common;
-use std::{io::{Seek, BufRead}, borrow::Cow, collections::HashMap};
+mod batch;
+mod borrowed;
+mod builtins;
+mod directive;
+mod escape;
+mod expand;
+mod expr;
+mod filter;
+mod format;
+mod front_matter;
+mod include;
+mod inherit;
+mod lazy;
+mod macros;
+mod serialize;
+mod strict;
+mod structured;
+mod whitespace;
+
+use std::{io::{Seek, BufRead}, borrow::Cow, collections::HashMap, path::{Path, PathBuf}, str::FromStr};
 
 use common::{bytes_to_string};
 use common::{res_err, res_ok, MyResult, wrapper, wrap_fn};
@@ -8,12 +27,122 @@ use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
-use simple_error::simple_error;
+use simple_error::{simple_error, SimpleError};
+
+pub use batch::*;
+pub use borrowed::*;
+pub use builtins::*;
+pub use directive::*;
+pub use escape::*;
+pub use expand::*;
+pub use expr::*;
+pub use filter::*;
+pub use format::*;
+pub use front_matter::*;
+pub use include::*;
+pub use lazy::*;
+pub use serialize::*;
+pub use structured::Layout;
+use whitespace::track_line;
 
 pub struct GenerateTemplate<'a>
 {
     pub template: &'a Template,
-    pub variables: &'a VariableMap
+    pub variables: &'a VariableMap,
+    pub options: GenerateOptions,
+}
+
+/// Knobs that change how [GenerateTemplate] renders a template.
+///
+/// Every field has a default, so a partial JSON object is a valid options object.
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct GenerateOptions {
+    /// How many levels of `${var}` references inside variable definitions
+    /// are expanded. `0`, the default, disables expansion altogether;
+    /// [DEFAULT_EXPAND_DEPTH] is a reasonable limit to opt in with.
+    pub max_expand_depth: usize,
+    /// Escaping for substitutions that neither pick their own through
+    /// `| escape(..)` nor inherit one from the template's [FrontMatter].
+    /// Usually derived from the output path with [EscapeMode::from_path].
+    pub escape: Option<EscapeMode>,
+    /// Also fail on variables that neither the template nor the definitions
+    /// it expands use, suggesting close matches for likely typos
+    pub strict: bool,
+    /// Source of the reserved `la.*` variables, such as `${la.now}`
+    #[serde(skip)]
+    pub builtins: Builtins,
+}
+
+/// The characters that delimit template constructs. Any char will do,
+/// e.g. `§«name»` with `sigil: '§', open: '«', close: '»'`.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct ParseOptions {
+    /// Starts every construct, `$` in `${name}`
+    pub sigil: char,
+    /// Opens a construct right after the sigil, `{` in `${name}`
+    pub open: char,
+    /// Closes a construct, `}` in `${name}`
+    pub close: char,
+    /// Emits the sigil literally when placed before it, `\` in `\$`,
+    /// and itself when doubled there, see [Escapes]
+    pub escape: char,
+    /// Which sequences [Self::escape] starts in literal text
+    pub escapes: Escapes,
+    /// Keep literals that are not UTF-8 as [Token::Bytes] rather than
+    /// failing; render them with [GenerateTemplate::generate_bytes]
+    pub binary: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self { sigil: '$', open: '{', close: '}', escape: '\\', escapes: Escapes::default(), binary: false }
+    }
+}
+
+/// The escape sequences of literal text, with the default delimiters
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Escapes {
+    /// Backslashes right before the sigil are halved, an odd one making it
+    /// literal: `\$` is a `$` and `\\${x}` a backslash and a substitution.
+    /// All others are kept as is, e.g. `C:\\dir`.
+    #[default]
+    Sigil,
+    /// As [Escapes::Sigil], plus `\n`, `\t`, `\r` and `\0` as in C; any
+    /// other sequence is an error
+    C,
+    /// The escape character is an ordinary one, so text cannot contain `${`
+    Off,
+}
+
+impl FromStr for Escapes {
+    type Err = SimpleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "sigil" => Ok(Self::Sigil),
+            "c" => Ok(Self::C),
+            "off" => Ok(Self::Off),
+            _ => Err(simple_error!("Unknown escapes {:?}, expected one of sigil, c, off", s)),
+        }
+    }
+}
+
+impl ParseOptions {
+    /// Rejects delimiters that would make the grammar ambiguous
+    pub fn validate(&self) -> MyResult<()> {
+        let delimiters = [self.sigil, self.open, self.close, self.escape];
+        if delimiters.iter().all_unique() && !delimiters.iter().any(|c| c.is_whitespace() || "#%-|".contains(*c)) {
+            res_ok(())
+        } else {
+            res_err(simple_error!(
+                "Delimiters must be distinct and neither whitespace nor one of '#%-|', got {:?}",
+                self
+            ))
+        }
+    }
 }
 
 impl <'t> GenerateTemplate<'t>
@@ -26,155 +155,489 @@ impl <'t> GenerateTemplate<'t>
     /// 
     /// Use [#Self::generate] for a more comprehensible result.
     pub fn dispatch(&self) -> MyResult<impl Iterator<Item=MyResult<Cow<'_, str>>>> {
-        res_ok(self.validate_ref()?
-            .apply())
+        let builtins = self.validate_ref()?;
+        res_ok(self.apply(builtins))
     }
     pub fn generate(&self) -> MyResult<String> {
         let res = self.dispatch()?;
         let (sucs, errs): (Vec<_>, Vec<_>) = res.collect::<Vec<_>>()
             .into_iter()
             .partition_result();
-        if !errs.is_empty() {
+        if errs.is_empty() {
             res_ok(sucs.join(""))
         } else {
             res_err(errs.iter().map(|err|err.to_string()).join("\n"))
         }        
     }
-    fn undefined_vars(&self) -> Vec<Cow<str>> {
-        self.template.symbols().iter()
-            .filter_map(|s| self.variables.get_defn(s).ok())
-            .collect::<Vec<_>>()
+    /// Lenient counterpart of [Self::generate]: substitutes the variables
+    /// that are defined and keeps the others, so that a later render with
+    /// more variables can finish the job
+    pub fn partial(&self) -> MyResult<ConcreteTemplate> {
+        let builtins = begin_render(self.variables, &self.options);
+        let mut symbols = Vec::new();
+        // kept variables are renumbered; there are no more of them than before
+        let mut keep = |var: u8| {
+            let name = &self.template.symbols()[var as usize];
+            let idx = symbols.iter().position(|s| s == name).unwrap_or_else(|| {
+                symbols.push(name.clone());
+                symbols.len() - 1
+            });
+            idx as u8
+        };
+        let mut tokens: Vec<Token> = Vec::with_capacity(self.template.tokens().len());
+        // the output after its last newline, kept variables counting as empty
+        let mut line = Vec::new();
+        let is_defined = |var: &u8| defines(self.variables, &self.options, &self.template.symbols()[*var as usize]);
+        for tok in self.template.tokens() {
+            let (var, filters) = match tok {
+                Token::Var(var) => (*var, &[][..]),
+                Token::Subst(subst) => (subst.var, subst.filters.as_slice()),
+                Token::Expr(expression) if expression.expr.vars().iter().all(is_defined) => {
+                    let value = self.evaluate(expression, &line, &builtins)?.into_owned();
+                    track_line(&mut line, value.as_bytes());
+                    push_str(&mut tokens, value);
+                    continue;
+                }
+                Token::Expr(expression) => {
+                    tokens.push(Token::Expr(Expression {
+                        expr: expression.expr.map_vars(&mut keep),
+                        filters: expression.filters.clone(),
+                    }));
+                    continue;
+                }
+                tok => {
+                    track_line(&mut line, tok.literal_bytes());
+                    tokens.push(tok.clone());
+                    continue;
+                }
+            };
+            if is_defined(&var) {
+                let value = self.substitute(var, filters, &line, &builtins)?.into_owned();
+                track_line(&mut line, value.as_bytes());
+                push_str(&mut tokens, value);
+            } else if filters.is_empty() {
+                tokens.push(Token::Var(keep(var)));
+            } else {
+                tokens.push(Token::Subst(Substitution { var: keep(var), filters: filters.to_vec() }));
+            }
+        }
+        res_ok(ConcreteTemplate {
+            front_matter: self.template.front_matter().clone(),
+            tokens,
+            symbols,
+        })
+    }
+    /// [Self::partial], written back as template text
+    pub fn partial_text(&self) -> MyResult<String> {
+        self.partial()?.to_source()
+    }
+    /// Starts a render once every symbol is known to be defined
+    pub(crate) fn validate_ref(&self) -> MyResult<RenderBuiltins<'_>> {
+        let builtins = begin_render(self.variables, &self.options);
+        validate_refs(self.template.symbols(), self.variables, &self.options)?;
+        res_ok(builtins)
     }
-    fn validate_ref(&self) -> MyResult<&Self> {
-        let undefined_vars = self.undefined_vars();
-        if !undefined_vars.is_empty() {
-            res_err(simple_error!("Missing definition: {:?}", undefined_vars))
+    /// [Self::generate] for templates parsed with [ParseOptions::binary]:
+    /// literals are copied byte for byte, substitutions are UTF-8
+    pub fn generate_bytes(&self) -> MyResult<Vec<u8>> {
+        let builtins = self.validate_ref()?;
+        let mut line = Vec::new();
+        let (sucs, errs): (Vec<_>, Vec<_>) = self.template.tokens().iter()
+            .map(|tok| {
+                let piece = match tok {
+                    Token::Bytes(bytes) => res_ok(Cow::from(bytes.as_slice())),
+                    tok => self.apply_token(tok, &line, &builtins).map(|s| match s {
+                        Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
+                        Cow::Owned(s) => Cow::Owned(s.into_bytes()),
+                    }),
+                };
+                if let Ok(piece) = &piece {
+                    track_line(&mut line, piece);
+                }
+                piece
+            })
+            .partition_result();
+        if errs.is_empty() {
+            res_ok(sucs.concat())
         } else {
-            res_ok(self)
+            res_err(errs.iter().map(|err|err.to_string()).join("\n"))
         }
     }
-    fn apply(&self) -> impl Iterator<Item=MyResult<Cow<'_, str>>> {
+    fn apply(&self, builtins: RenderBuiltins<'t>) -> impl Iterator<Item=MyResult<Cow<'_, str>>> {
+        // the output after its last newline, for `indent`
+        let mut line = Vec::new();
         self.template.tokens().iter()
-            .map(|tok| match tok {
-                Token::Str(s) => res_ok(Cow::from(s)),
-                Token::Var(idx) => self.template.symbols()
-                    .get(*idx as usize)
-                    .ok_or_else(||simple_error!("Idx out of bounds: {}", idx).into())
-                    .and_then(|var_name| {
-                        self.variables.get_defn(var_name)
-                    })
+            .map(move |tok| {
+                let piece = self.apply_token(tok, &line, &builtins);
+                if let Ok(piece) = &piece {
+                    track_line(&mut line, piece.as_bytes());
+                }
+                piece
             })
     }
+    /// `tok` rendered after `line`, the output since the last newline
+    fn apply_token(&self, tok: &'t Token, line: &[u8], builtins: &RenderBuiltins) -> MyResult<Cow<'t, str>> {
+        match tok {
+            Token::Str(s) => res_ok(Cow::from(s)),
+            Token::Bytes(_) => res_err(simple_error!("Template holds binary literals, render it with generate_bytes")),
+            Token::Var(idx) => self.substitute(*idx, &[], line, builtins),
+            Token::Subst(subst) => self.substitute(subst.var, &subst.filters, line, builtins),
+            Token::Expr(expression) => self.evaluate(expression, line, builtins),
+            Token::Tag(tag) => res_err(simple_error!("Unsupported block tag {:?}", tag.name)),
+        }
+    }
+    /// Definition of the `idx`-th symbol after `filters` and escaping
+    fn substitute(&self, idx: u8, filters: &[Filter], line: &[u8], builtins: &RenderBuiltins) -> MyResult<Cow<'t, str>> {
+        let escape = escape_after(filters, self.default_escape());
+        substitute(self.variables, &self.options, builtins, self.symbol(idx)?, filters, escape, line)
+    }
+    /// Value of `expression` after its filters and escaping
+    fn evaluate(&self, expression: &Expression, line: &[u8], builtins: &RenderBuiltins) -> MyResult<Cow<'t, str>> {
+        let escape = escape_after(&expression.filters, self.default_escape());
+        evaluate(self.variables, &self.options, builtins, self.template.symbols(), expression, escape, line)
+    }
+    fn symbol(&self, idx: u8) -> MyResult<&'t str> {
+        self.template.symbols()
+            .get(idx as usize)
+            .map(String::as_str)
+            .ok_or_else(|| simple_error!("Idx out of bounds: {}", idx).into())
+    }
+    /// Escaping of substitutions that do not pick their own
+    fn default_escape(&self) -> Option<EscapeMode> {
+        self.template.front_matter().escape.or(self.options.escape)
+    }
 }
 
-#[derive(Deserialize, Serialize, Debug, PartialEq, Eq)]
-struct TemplateParser<R> 
-    where R: BufRead+Seek
-{
-    // on creation
-    template: R,
-    #[serde(default="sym")]
-    sym: u8,
-    #[serde(default="escape")]
-    escape: u8,
-    // on dispatch
-    buf: Vec<u8>,
-    tokens: Vec<Token>,
-    symbs: Vec<String>,
+/// Appends a literal, merging it with a literal right before
+pub(crate) fn push_str(tokens: &mut Vec<Token>, s: String) {
+    match tokens.last_mut() {
+        Some(Token::Str(last)) => last.push_str(&s),
+        _ => tokens.push(Token::Str(s)),
+    }
 }
-const fn sym()->u8{b'$'}
-const fn escape()->u8{b'\\'}
 
-#[derive(Debug)]
-enum SeekSymbol {
-    EndOfFile,
-    Symbol,
-    Escape
+/// Index of `name` in `symbols`, appended if missing
+pub(crate) fn intern(symbols: &mut Vec<String>, name: &str) -> MyResult<u8> {
+    let idx = match symbols.iter().position(|s| s == name) {
+        Some(idx) => idx,
+        None => {
+            symbols.push(name.to_string());
+            symbols.len() - 1
+        }
+    };
+    u8::try_from(idx)
+        .map_err(|_| simple_error!("Templates may only declare up to {} variables", u8::MAX as usize + 1).into())
 }
 
-impl <R> /*FnOnce()->MyResult<Template> for*/ TemplateParser<R>
-    where R: BufRead+Seek
-{
-    fn call(mut self) -> MyResult<ConcreteTemplate> {
-        loop {
-            let (symb, token) = self.next_token()?;
-            log::debug!("Next token: symb: {symb:?}, token: {token:?}");
-            self.tokens.push(token);
-            match symb {
-                SeekSymbol::EndOfFile => break res_ok(()),
-                SeekSymbol::Escape => continue,
-                _ => {}
-            }
-            self.buf.clear();
-            // we now hit the $ symbol, determine the var name
-            let var_name = self.var_name()?;
-            log::debug!("Var name: {var_name}");
-            self.tokens.push(Token::Var(self.symbs.len() as u8));
-            self.symbs.push(var_name);
-        }?;
-        res_ok(ConcreteTemplate {
-            symbols: self.symbs,
-            tokens: self.tokens
-        })
+/// Definition of `var_name`, from `builtins` for `la.*` names, expanding
+/// references inside it up to `max_expand_depth` levels
+pub(crate) fn lookup<'v>(
+    variables: &'v VariableMap,
+    options: &GenerateOptions,
+    builtins: &RenderBuiltins,
+    var_name: &str,
+) -> MyResult<Cow<'v, str>> {
+    if is_builtin(var_name) {
+        return builtins.get(var_name).map(Cow::Owned);
+    }
+    if options.max_expand_depth == 0 {
+        return variables.get_defn(var_name);
+    }
+    ExpandVariables::new(variables, options.max_expand_depth)
+        .with_builtins(builtins)
+        .expand(var_name)
+}
+
+/// Definition of `var_name` after `filters` and `escape`, written after `output`
+pub(crate) fn substitute<'v>(
+    variables: &'v VariableMap,
+    options: &GenerateOptions,
+    builtins: &RenderBuiltins,
+    var_name: &str,
+    filters: &[Filter],
+    escape: Option<EscapeMode>,
+    output: &[u8],
+) -> MyResult<Cow<'v, str>> {
+    let value = lookup(variables, options, builtins, var_name)?;
+    finish(value, filters, lookup_json(variables, var_name), escape, output)
+}
+
+/// Value of `expression` after its filters and `escape`, written after `output`.
+/// Its variables index `symbols`.
+pub(crate) fn evaluate<'v, S: AsRef<str>>(
+    variables: &VariableMap,
+    options: &GenerateOptions,
+    builtins: &RenderBuiltins,
+    symbols: &[S],
+    expression: &Expression,
+    escape: Option<EscapeMode>,
+    output: &[u8],
+) -> MyResult<Cow<'v, str>> {
+    let value = expression.expr.eval(&mut |var| {
+        let name = symbols.get(var as usize).ok_or_else(|| simple_error!("Idx out of bounds: {}", var))?;
+        lookup(variables, options, builtins, name.as_ref())
+    })?;
+    finish(Cow::Owned(value.to_string()), &expression.filters, Some(&value.to_json()), escape, output)
+}
+
+/// `value` after `filters` and `escape`, written after `output`
+fn finish<'v>(
+    value: Cow<'v, str>,
+    filters: &[Filter],
+    json: Option<&Value>,
+    escape: Option<EscapeMode>,
+    output: &[u8],
+) -> MyResult<Cow<'v, str>> {
+    let value = apply_filters_at(filters, value, json, output)?;
+    res_ok(match escape {
+        Some(mode) => mode.escape_at(value, output),
+        None => value,
+    })
+}
+
+/// The JSON value behind `var_name`, see [VariableTrait::get_json]
+pub(crate) fn lookup_json<'v>(variables: &'v VariableMap, var_name: &str) -> Option<&'v Value> {
+    match is_builtin(var_name) {
+        true => None,
+        false => variables.get_json(var_name),
+    }
+}
+
+/// Whether [lookup] has a definition for `var_name`
+pub(crate) fn defines(variables: &VariableMap, options: &GenerateOptions, var_name: &str) -> bool {
+    if is_builtin(var_name) {
+        options.builtins.defines(var_name)
+    } else {
+        variables.defines(var_name)
+    }
+}
+
+/// Starts a render: values of [LazyVariables] and the time and UUID of
+/// [Builtins] are computed afresh, then kept for the rest of the render
+pub(crate) fn begin_render<'o>(variables: &VariableMap, options: &'o GenerateOptions) -> RenderBuiltins<'o> {
+    variables.begin_render();
+    options.builtins.begin_render()
+}
+
+/// Checks that `variables` define every symbol and, with
+/// [GenerateOptions::strict], that every variable is used
+pub(crate) fn validate_refs<S: AsRef<str>>(symbols: &[S], variables: &VariableMap, options: &GenerateOptions) -> MyResult<()> {
+    let mut problems = Vec::new();
+    let undefined_vars = symbols.iter()
+        .map(AsRef::as_ref)
+        .filter(|s| !defines(variables, options, s))
+        .collect::<Vec<_>>();
+    if !undefined_vars.is_empty() {
+        problems.push(format!("Missing definition: {:?}", undefined_vars));
     }
-    pub fn new(template: R, sym: Option<u8>, escape: Option<u8>)->Self {
-        Self { 
-            template, 
-            sym: sym.unwrap_or(b'$'), 
-            escape: escape.unwrap_or(b'\\'), 
-            buf: Default::default(), 
-            tokens: Default::default(), 
-            symbs: Default::default() 
+    let unused_vars = if options.strict {
+        strict::unused_vars(symbols, variables, options.max_expand_depth)
+    } else {
+        Vec::new()
+    };
+    if !unused_vars.is_empty() {
+        problems.push(format!("Unused variables: {}", strict::describe_unused(&unused_vars, symbols)));
+    }
+    if problems.is_empty() {
+        res_ok(())
+    } else {
+        res_err(problems.join("\n"))
+    }
+}
+
+/// Splits `subject:spec | filter..` into the subject, a variable name or an
+/// [Expr], and its [Filter]s, the [FormatSpec] coming first
+fn parse_substitution(content: &str) -> MyResult<(&str, Vec<Filter>)> {
+    let (subject, mut rest) = content.split_at(subject_end(content));
+    let mut filters = Vec::new();
+    if let Some(after) = rest.strip_prefix(':') {
+        let spec_end = after.find('|').unwrap_or(after.len());
+        filters.push(Filter::Format(FormatSpec::parse(after[..spec_end].trim())?));
+        rest = &after[spec_end..];
+    }
+    if let Some(after) = rest.strip_prefix('|') {
+        for filter in split_unquoted(after, b'|') {
+            filters.push(Filter::parse(filter)?);
         }
     }
-    fn var_name(&mut self) -> MyResult<String> {
-        unimplemented!("get varname after having found $ here");
-    }
-    fn next_token(&mut self) -> MyResult<(SeekSymbol, Token)> {
-        self.template.read_until(self.sym, &mut self.buf)?;
-        let symb_chr = self.buf.pop()
-            // If it's not a self.sym, then we took the last chr of EOF, return it
-            .and_then(|b| if b == self.sym {Some(b)} else {self.buf.push(b);None});
-        log::debug!("Found {}u8; symb_chr: {symb_chr:?}", self.sym);
-        if matches!(symb_chr, None) {
-            // This will be the last self.token that is a literal.
-            return res_ok((SeekSymbol::EndOfFile, Token::from_bytes(&self.buf)?));
+    res_ok((subject.trim(), filters))
+}
+
+/// Where the subject of a substitution ends: at the first `:` or `|`
+/// outside string literals and `||`
+fn subject_end(content: &str) -> usize {
+    let bytes = content.as_bytes();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        match bytes[pos] {
+            b'"' => pos += closing_quote(&content[pos..]).unwrap_or(content.len() - pos),
+            b'|' if bytes.get(pos + 1) == Some(&b'|') => pos += 1,
+            b':' | b'|' => return pos,
+            _ => {}
         }
-        let symb = symb_chr.unwrap();
-        let chr_before = self.buf.pop();
-        log::debug!("chr_before: {chr_before:?}");
-        if let Some(b) = chr_before {
-            if b == self.escape {
-                self.buf.push(symb);
-                return res_ok((SeekSymbol::Escape, Token::from_bytes(&self.buf)?));
+        pos += 1;
+    }
+    content.len()
+}
+
+/// Splits on `sep`, except within string literals
+pub(crate) fn split_unquoted(content: &str, sep: u8) -> Vec<&str> {
+    let bytes = content.as_bytes();
+    let (mut parts, mut start, mut pos) = (Vec::new(), 0, 0);
+    while pos < bytes.len() {
+        match bytes[pos] {
+            b'"' => pos += closing_quote(&content[pos..]).unwrap_or(content.len() - pos),
+            byte if byte == sep => {
+                parts.push(&content[start..pos]);
+                start = pos + 1;
             }
-            self.buf.push(b);
+            _ => {}
         }
-        res_ok((SeekSymbol::Symbol, Token::from_bytes(&self.buf)?))
+        pos += 1;
     }
+    parts.push(&content[start..]);
+    parts
+}
+
+/// A variable name is a non-empty sequence of segments made of
+/// alphanumerics (any script) and `_`, joined by `.`
+pub(crate) fn is_var_name(name: &str) -> bool {
+    name.split('.')
+        .all(|seg| !seg.is_empty() && seg.chars().all(|c| c.is_alphanumeric() || c == '_'))
 }
 
 pub fn parse_template<R>(template: R)
     -> MyResult<ConcreteTemplate> 
     where R: BufRead + Seek 
 {
-    TemplateParser::new(template, None, None)
+    parse_template_with(template, Default::default())
+}
+
+/// [parse_template] with custom delimiters
+pub fn parse_template_with<R>(template: R, options: ParseOptions)
+    -> MyResult<ConcreteTemplate> 
+    where R: BufRead + Seek 
+{
+    TemplateParser::new(template, options)
         .call()
 }
 
+/// Parses a whole reader: reads it into memory and lexes it with
+/// [parse_borrowed], owning the result
+#[derive(Debug)]
+struct TemplateParser<R>
+    where R: BufRead + Seek
+{
+    template: R,
+    options: ParseOptions,
+}
+
+impl <R> TemplateParser<R>
+    where R: BufRead + Seek
+{
+    fn new(template: R, options: ParseOptions) -> Self {
+        Self { template, options }
+    }
+    fn call(mut self) -> MyResult<ConcreteTemplate> {
+        let mut src = Vec::new();
+        self.template.read_to_end(&mut src)?;
+        parse_borrowed(&src, self.options).map(BorrowedTemplate::into_owned)
+    }
+}
+
+/// [parse_template_with] that goes on after syntax errors, returning what
+/// could be parsed along with every error, see [parse_borrowed_recovering]
+pub fn parse_template_recovering<R>(mut template: R, options: ParseOptions)
+    -> MyResult<(ConcreteTemplate, Vec<Diagnostic>)>
+    where R: BufRead
+{
+    let mut src = Vec::new();
+    template.read_to_end(&mut src)?;
+    let (template, diagnostics) = parse_borrowed_recovering(&src, options)?;
+    res_ok((template.into_owned(), diagnostics))
+}
+
 pub fn generate_template<T, V>(template: T, variables: V) 
     -> MyResult<String> 
     where
         T: Into<Template>,
         V: Into<VariableMap>
 {
-    GenerateTemplate {template:&template.into(), variables:&variables.into()}.generate()
+    GenerateTemplate {
+        template: &template.into(),
+        variables: &variables.into(),
+        options: Default::default(),
+    }.generate()
 }
 
-#[derive(Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
 pub enum Token {
     Str(String),
-    Var(u8)
+    /// A literal that is not UTF-8, see [ParseOptions::binary]
+    Bytes(Vec<u8>),
+    Var(u8),
+    /// A variable that goes through [Filter]s, i.e. `${name | escape("json")}`
+    Subst(Substitution),
+    /// An expression over variables, i.e. `${replicas * 2}`
+    Expr(Expression),
+    Tag(Tag),
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Substitution {
+    /// Index into [TemplateTrait::symbols]
+    pub var: u8,
+    pub filters: Vec<Filter>,
+}
+
+/// A block tag, i.e. `${% name args %}`
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Tag {
+    pub name: String,
+    /// Everything after the name, trimmed
+    pub args: String,
+    /// For `include` and `call`, the text before the tag on its line,
+    /// blanked to spaces, by which `indent` shifts the inserted lines
+    #[serde(default)]
+    pub indent: String,
+    /// Where the tag starts in the template it was parsed from, to locate
+    /// its errors
+    #[serde(default)]
+    pub span: Option<Span>,
+    /// The template the tag was read from, once inlined into another, for
+    /// the path of its errors
+    #[serde(default)]
+    pub origin: Option<PathBuf>,
+}
+
+/// Tags are equal wherever they are
+impl PartialEq for Tag {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.args == other.args && self.indent == other.indent
+    }
+}
+
+impl Eq for Tag {}
+
+impl Tag {
+    pub fn parse(content: &str) -> MyResult<Self> {
+        let content = content.trim();
+        let (name, args) = content.split_once(char::is_whitespace).unwrap_or((content, ""));
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return res_err(simple_error!("Invalid block tag name {:?}", name));
+        }
+        res_ok(Self { name: name.to_string(), args: args.trim().to_string(), indent: String::new(), span: None, origin: None })
+    }
+    /// `tok`, noting `path` as the origin of a tag not inlined from elsewhere
+    pub(crate) fn from_file(tok: &Token, path: &Path) -> Token {
+        match tok {
+            Token::Tag(tag) => Token::Tag(Tag {
+                origin: Some(tag.origin.clone().unwrap_or_else(|| path.to_path_buf())),
+                ..tag.clone()
+            }),
+            tok => tok.clone(),
+        }
+    }
 }
 #[enum_dispatch]
 pub trait TemplateTrait {
@@ -182,6 +645,7 @@ pub trait TemplateTrait {
     // fn tokens_mut(&mut self) -> &mut Vec<Token>;
     fn symbols(&self) -> &Vec<String>;
     // fn symbols_mut(&mut self) -> &mut Vec<String>;
+    fn front_matter(&self) -> &FrontMatter;
 }
 
 #[enum_dispatch(TemplateTrait)]
@@ -197,24 +661,98 @@ pub trait VariableTrait {
     fn get_defn<AnyStr: AsRef<str>>(&self, key: AnyStr) -> MyResult<Cow<'_, str>> {
         self._get_defn(key.as_ref())
     }
+    /// Whether `key` has a definition, without failing on one that cannot
+    /// be computed; that error is reported when rendering
+    fn defines(&self, key: &str) -> bool {
+        self._get_defn(key).is_ok()
+    }
+    /// Every variable name defined, or `None` when the map cannot list
+    /// them, in which case [GenerateOptions::strict] does not look for
+    /// unused variables
+    fn names(&self) -> Option<Vec<&str>> {
+        None
+    }
+    /// The JSON value `key` is defined by, if the map holds JSON, so that
+    /// `to_yaml` and `to_json` keep its type
+    fn get_json(&self, _key: &str) -> Option<&Value> {
+        None
+    }
 }
 #[enum_dispatch(VariableTrait)]
 #[derive(Debug)]
 pub enum VariableMap 
 {
     HashMapStd(HashMap<String, String>),
-    SerdeValue(Value)
+    SerdeValue(Value),
+    Lazy(LazyVariables),
 }
 
 
 // Implementations
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ConcreteTemplate {
+    /// Settings declared at the top of the template
+    front_matter: FrontMatter,
     /// All of the tokens that makes up the template
     tokens: Vec<Token>,
     /// Contains the names of the variables declared in given template
     symbols: Vec<String>
 }
+impl ConcreteTemplate {
+    /// Writes the template back in `${...}` syntax, escaping literal sigils
+    /// and the backslashes that would read as escapes, so that parsing the
+    /// result with the default [ParseOptions] yields the same template.
+    /// Comments and trimmed whitespace are not restored.
+    pub fn to_source(&self) -> MyResult<String> {
+        let ParseOptions { sigil, escape, .. } = ParseOptions::default();
+        let mut src = self.front_matter.to_string();
+        let name = |var: &u8| self.symbols.get(*var as usize)
+            .ok_or_else(|| simple_error!("Idx out of bounds: {}", var));
+        // a leading `-` would read as a trim marker, `${ -x}` is a negation
+        let expr = |expression: &Expression| expression.expr.to_source(&self.symbols).map(|src| match src.starts_with('-') {
+            true => format!(" {}", src),
+            false => src,
+        });
+        for (idx, tok) in self.tokens.iter().enumerate() {
+            match tok {
+                Token::Str(s) => {
+                    for (at, c) in s.char_indices() {
+                        // escapes are halved before a sigil, and any token
+                        // after a literal starts with one
+                        let before_sigil = || match s[at..].trim_start_matches(escape).chars().next() {
+                            Some(next) => next == sigil,
+                            None => idx + 1 < self.tokens.len(),
+                        };
+                        if c == sigil || (c == escape && before_sigil()) {
+                            src.push(escape);
+                        }
+                        src.push(c);
+                    }
+                }
+                Token::Bytes(_) => return res_err(simple_error!("Binary literals cannot be written back as template text")),
+                Token::Var(var) => src.push_str(&format!("${{{}}}", name(var)?)),
+                Token::Subst(subst) => src.push_str(&format!(
+                    "${{{} | {}}}",
+                    name(&subst.var)?,
+                    subst.filters.iter().join(" | ")
+                )),
+                Token::Expr(expression) if expression.filters.is_empty() => src.push_str(&format!(
+                    "${{{}}}",
+                    expr(expression)?
+                )),
+                Token::Expr(expression) => src.push_str(&format!(
+                    "${{{} | {}}}",
+                    expr(expression)?,
+                    expression.filters.iter().join(" | ")
+                )),
+                Token::Tag(tag) if tag.args.is_empty() => src.push_str(&format!("${{% {} %}}", tag.name)),
+                Token::Tag(tag) => src.push_str(&format!("${{% {} {} %}}", tag.name, tag.args)),
+            }
+        }
+        res_ok(src)
+    }
+}
+
 impl TemplateTrait for ConcreteTemplate {
     fn tokens(&self) ->  &Vec<Token> {
         &self.tokens
@@ -226,7 +764,11 @@ impl TemplateTrait for ConcreteTemplate {
         &self.symbols
     }
 
-    // fn symbols_mut(&mut self) ->  &mut Vec<String> {&mut self.symbols}
+    // fn symbols_mut(&mut self) -> &mut Vec<String> {&mut self.symbols}
+
+    fn front_matter(&self) -> &FrontMatter {
+        &self.front_matter
+    }
 }
 
 wrapper!(
@@ -237,6 +779,7 @@ pub BufReadTemplate wraps ConcreteTemplate
 impl TemplateTrait for BufReadTemplate {
     wrap_fn!(fn tokens(&self) -> &Vec<Token>);
     wrap_fn!(fn symbols(&self) -> &Vec<String>);
+    wrap_fn!(fn front_matter(&self) -> &FrontMatter);
     // wrap_fn!(fn tokens_mut(&mut self) -> &mut Vec<Token>);
     // wrap_fn!(fn symbols_mut(&mut self) -> &mut Vec<String>);
 }
@@ -257,6 +800,9 @@ impl BufReadTemplate {
     pub fn new<R>(read: R) -> MyResult<Self> where R: BufRead + Seek {
         Ok(Self(parse_template(read)?))
     }
+    pub fn with_options<R>(read: R, options: ParseOptions) -> MyResult<Self> where R: BufRead + Seek {
+        Ok(Self(parse_template_with(read, options)?))
+    }
 }
 
 impl <AnyStr> VariableTrait for HashMap<String, AnyStr> 
@@ -264,9 +810,12 @@ impl <AnyStr> VariableTrait for HashMap<String, AnyStr>
 {
     fn _get_defn<'a>(& 'a self,key: &str) -> MyResult<Cow< 'a,str>> {
         self.get(key)
-            .ok_or_else(||simple_error!("Var {} expected, but not defined").into())
+            .ok_or_else(||simple_error!("Var {} expected, but not defined", key).into())
             .map(|v| v.as_ref().into())
     }
+    fn names(&self) -> Option<Vec<&str>> {
+        Some(self.keys().map(String::as_str).collect())
+    }
 }
 
 impl <AnyStr> VariableTrait for HashMap<&str, AnyStr>
@@ -274,18 +823,35 @@ impl <AnyStr> VariableTrait for HashMap<&str, AnyStr>
 {
     fn _get_defn<'a>(& 'a self,key: &str) -> MyResult<Cow<'a,str>> {
         self.get(key)
-            .ok_or_else(||simple_error!("Var {} expected, but not defined").into())
+            .ok_or_else(||simple_error!("Var {} expected, but not defined", key).into())
             .map(|v| v.as_ref().into())
     }
+    fn names(&self) -> Option<Vec<&str>> {
+        Some(self.keys().copied().collect())
+    }
 }
 
 impl VariableTrait for Value {
     fn _get_defn< 'a>(& 'a self,key: &str) -> MyResult<Cow< 'a,str>> {
         self.as_object().ok_or_else(||simple_error!("Given json is not str->str"))
-            .and_then(|m| m.get(key).ok_or_else(||simple_error!("No such variable")))
-            .and_then(|v| v.as_str().ok_or_else(||simple_error!("The mapping to value({:?}) is not str", v)))
+            .and_then(|m| m.get(key).ok_or_else(||simple_error!("No such variable: {}", key)))
+            .and_then(|v| match v {
+                Value::String(s) => Ok(Cow::from(s.as_str())),
+                // numbers and bools read as written, e.g. for format specs
+                Value::Number(_) | Value::Bool(_) => Ok(Cow::from(v.to_string())),
+                // collections as compact JSON, e.g. for `to_yaml`
+                Value::Array(_) | Value::Object(_) => Ok(Cow::from(v.to_string())),
+                Value::Null => Err(simple_error!("The mapping to {} is null", key)),
+            })
             .map_err(|e|e.into())
-            .map(|s| s.into())
+    }
+    fn names(&self) -> Option<Vec<&str>> {
+        Some(self.as_object()
+            .map(|m| m.keys().map(String::as_str).collect())
+            .unwrap_or_default())
+    }
+    fn get_json(&self, key: &str) -> Option<&Value> {
+        self.as_object()?.get(key)
     }
 }
 
@@ -293,6 +859,14 @@ impl Token {
     pub fn from_bytes(bytes: &[u8]) -> MyResult<Self> {
         bytes_to_string(bytes).map(Token::Str)
     }
+    /// Bytes of a [Token::Str] or [Token::Bytes] literal, empty otherwise
+    pub fn literal_bytes(&self) -> &[u8] {
+        match self {
+            Token::Str(s) => s.as_bytes(),
+            Token::Bytes(bytes) => bytes,
+            _ => &[],
+        }
+    }
 }
 
 impl <AnyStr> From<AnyStr> for Token
@@ -0,0 +1,323 @@
+//! Macros: fragments with parameters, defined once and called as often as
+//! needed:
+//!
+//! ```text
+//! ${% macro exec(name, command) %}
+//! - name: ${name}
+//!   command: multipass exec ${vm} -- ${command}
+//! ${% endmacro %}
+//! ${% call exec("Back up keys", "cp keys keys.bck") %}
+//! ${% call exec("Restart " + service, "systemctl restart " + service) %}
+//! ```
+//!
+//! Variables of a macro body other than its parameters are those of the
+//! output. `${% import "tasks.t.yml" %}` makes the macros of another
+//! template callable, the rest of it is ignored. With `indent` after the
+//! arguments, or `indent = true` in the front matter, a call shifts the
+//! lines of the body to the column the tag starts at.
+//!
+//! [ConcreteTemplate::resolve_macros] expands calls before rendering.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use common::{res_err, res_ok, AnyErr, MyResult};
+use simple_error::simple_error;
+
+use crate::{
+    closing_quote,
+    include::{bind_expr, quoted_arg, rebind, splice},
+    is_var_name, push_str, ConcreteTemplate, Diagnostic, Expr, IncludePath, Tag, TemplateTrait, Token,
+};
+
+/// Nesting limit for macro calls
+const MAX_CALL_DEPTH: usize = 64;
+
+const MACRO_TAGS: [&str; 4] = ["macro", "endmacro", "call", "import"];
+
+struct Definition {
+    params: Vec<String>,
+    tokens: Vec<Token>,
+    /// Variables of `tokens`, those of the defining template
+    symbols: Vec<String>,
+    /// The template defining it
+    origin: PathBuf,
+}
+
+impl ConcreteTemplate {
+    /// Expands macro calls, recursively. `path` is where this template was
+    /// read from; imported templates are found through `search` and read
+    /// with `load`, and may extend or include others.
+    ///
+    /// ```
+    /// # use la_template_base::*;
+    /// # use std::{io::Cursor, path::Path};
+    /// let page = parse_template(Cursor::new(
+    ///     "${% macro greet(who) %}hello ${who}${% endmacro %}${% call greet(name) %}, ${% call greet(\"you\") %}",
+    /// )).unwrap();
+    /// let resolved = page.resolve_macros(Path::new("page.t.txt"), &IncludePath::new(), |_| unreachable!()).unwrap();
+    /// assert_eq!(resolved.to_source().unwrap(), "hello ${name}, hello ${\"you\"}");
+    /// ```
+    pub fn resolve_macros<F>(self, path: &Path, search: &IncludePath, mut load: F) -> MyResult<Self>
+    where
+        F: FnMut(&Path) -> MyResult<ConcreteTemplate>,
+    {
+        if !self.tokens().iter().any(|tok| matches!(tok, Token::Tag(tag) if MACRO_TAGS.contains(&tag.name.as_str()))) {
+            return res_ok(self);
+        }
+        let mut expander = Expander {
+            search,
+            load: &mut load,
+            indent_all: self.front_matter.indent,
+            macros: HashMap::new(),
+            imports: vec![path.to_path_buf()],
+            calls: Vec::new(),
+            symbols: Vec::new(),
+            tokens: Vec::new(),
+        };
+        let body = expander.define(&self, path)?;
+        expander.expand(&body, self.symbols(), &HashMap::new())?;
+        res_ok(ConcreteTemplate {
+            front_matter: self.front_matter,
+            tokens: expander.tokens,
+            symbols: expander.symbols,
+        })
+    }
+}
+
+/// `name(a, b)`, the signature after `macro`
+fn parse_signature(args: &str) -> MyResult<(&str, Vec<String>)> {
+    let (name, params, rest) = parse_call(args, "macro")?;
+    if !rest.is_empty() {
+        return res_err(simple_error!("Unexpected {:?} after the parameters of macro {}", rest, name));
+    }
+    let params = params.into_iter()
+        .map(|param| match is_var_name(param) && !param.contains('.') {
+            true => res_ok(param.to_string()),
+            false => res_err(simple_error!("Invalid parameter {:?} of macro {}", param, name)),
+        })
+        .collect::<MyResult<Vec<_>>>()?;
+    if let Some(param) = params.iter().enumerate().find_map(|(idx, p)| params[..idx].contains(p).then_some(p)) {
+        return res_err(simple_error!("Parameter {} of macro {} is given twice", param, name));
+    }
+    res_ok((name, params))
+}
+
+/// `name(a, b) rest`, the macro name, the trimmed items between the
+/// parentheses and what follows them, trimmed
+fn parse_call<'a>(args: &'a str, tag: &str) -> MyResult<(&'a str, Vec<&'a str>, &'a str)> {
+    let expected = || simple_error!("Expected `name(...)` after {}, got {:?}", tag, args);
+    let (name, rest) = args.split_once('(').ok_or_else(expected)?;
+    let name = name.trim();
+    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return res_err(expected());
+    }
+    let close = top_level(rest, b')')
+        .into_iter()
+        .next()
+        .ok_or_else(|| simple_error!("Missing ')' after the arguments of {} {}", tag, name))?;
+    let (inner, after) = (&rest[..close], &rest[close + 1..]);
+    let mut items = Vec::new();
+    let mut start = 0;
+    for comma in top_level(inner, b',').into_iter().chain([inner.len()]) {
+        items.push(inner[start..comma].trim());
+        start = comma + 1;
+    }
+    if items == [""] {
+        items.clear();
+    }
+    res_ok((name, items, after.trim()))
+}
+
+/// Positions of `sep` in `src` outside string literals and parentheses
+/// opened in `src`; a `)` closing one opened before `src` ends the search
+fn top_level(src: &str, sep: u8) -> Vec<usize> {
+    let bytes = src.as_bytes();
+    let (mut found, mut depth, mut pos) = (Vec::new(), 0usize, 0);
+    while pos < bytes.len() {
+        match bytes[pos] {
+            b'"' => pos += closing_quote(&src[pos..]).unwrap_or(src.len() - pos),
+            byte if byte == sep && depth == 0 => found.push(pos),
+            b'(' => depth += 1,
+            b')' if depth == 0 => break,
+            b')' => depth -= 1,
+            _ => {}
+        }
+        pos += 1;
+    }
+    found
+}
+
+struct Expander<'s, F> {
+    search: &'s IncludePath,
+    load: &'s mut F,
+    /// [crate::FrontMatter::indent] of the template being resolved
+    indent_all: bool,
+    macros: HashMap<String, Definition>,
+    /// The templates being imported, outermost first
+    imports: Vec<PathBuf>,
+    /// The macros being expanded, outermost first
+    calls: Vec<String>,
+    symbols: Vec<String>,
+    tokens: Vec<Token>,
+}
+
+impl<'s, F> Expander<'s, F>
+where
+    F: FnMut(&Path) -> MyResult<ConcreteTemplate>,
+{
+    /// Registers the macros `template` defines and imports, and returns
+    /// its other tokens
+    fn define(&mut self, template: &ConcreteTemplate, path: &Path) -> MyResult<Vec<Token>> {
+        let mut body = Vec::new();
+        let mut defining: Option<(String, Definition)> = None;
+        for tok in template.tokens() {
+            let tok = &Tag::from_file(tok, path);
+            match (tok, &mut defining) {
+                (Token::Tag(tag), Some((name, _))) if tag.name == "macro" || tag.name == "import" => {
+                    return res_err(simple_error!("{} inside macro {}", tag.name, name));
+                }
+                (Token::Tag(tag), Some(_)) if tag.name == "endmacro" => {
+                    let (name, definition) = defining.take().unwrap();
+                    if !tag.args.is_empty() && tag.args != name {
+                        return res_err(simple_error!("endmacro {} closes macro {}", tag.args, name));
+                    }
+                    self.register(name, definition)?;
+                }
+                (tok, Some((_, definition))) => definition.tokens.push(tok.clone()),
+                (Token::Tag(tag), None) if tag.name == "macro" => {
+                    let (name, params) = parse_signature(&tag.args).map_err(|e| located(tag, e))?;
+                    defining = Some((name.to_string(), Definition {
+                        params,
+                        tokens: Vec::new(),
+                        symbols: template.symbols().clone(),
+                        origin: path.to_path_buf(),
+                    }));
+                }
+                (Token::Tag(tag), None) if tag.name == "endmacro" => {
+                    return res_err(simple_error!("endmacro without a macro"));
+                }
+                (Token::Tag(tag), None) if tag.name == "import" => self.import(tag)?,
+                (tok, None) => body.push(tok.clone()),
+            }
+        }
+        match defining {
+            Some((name, _)) => res_err(simple_error!("Missing endmacro for macro {}", name)),
+            None => res_ok(body),
+        }
+    }
+    fn register(&mut self, name: String, definition: Definition) -> MyResult<()> {
+        if let Some(defined) = self.macros.get(&name) {
+            return res_err(simple_error!(
+                "Macro {} is defined twice, in {} and {}",
+                name,
+                defined.origin.display(),
+                definition.origin.display()
+            ));
+        }
+        self.macros.insert(name, definition);
+        res_ok(())
+    }
+    fn import(&mut self, tag: &Tag) -> MyResult<()> {
+        let (name, rest) = quoted_arg(&tag.args, "import")?;
+        if !rest.is_empty() {
+            return res_err(simple_error!("Unexpected {:?} after the path of import", rest));
+        }
+        let (path, template) = self.search.load("import", &name, &self.imports, self.load)?;
+        let template = template
+            .resolve_inheritance(&path, &mut *self.load)?
+            .resolve_includes(&path, self.search, &mut *self.load)?;
+        self.imports.push(path.clone());
+        self.define(&template, &path)?;
+        self.imports.pop();
+        res_ok(())
+    }
+    /// Appends `tokens`, whose variables are `names`, bound to expressions
+    /// over the output's variables by `bindings`, expanding calls
+    fn expand(&mut self, tokens: &[Token], names: &[String], bindings: &HashMap<String, Expr>) -> MyResult<()> {
+        for tok in tokens {
+            match tok {
+                Token::Str(s) => push_str(&mut self.tokens, s.clone()),
+                Token::Tag(tag) if tag.name == "call" => self.call(tag, bindings)?,
+                tok => {
+                    let tok = rebind(tok, names, bindings, &mut self.symbols)?;
+                    self.tokens.push(tok);
+                }
+            }
+        }
+        res_ok(())
+    }
+    fn call(&mut self, tag: &Tag, bindings: &HashMap<String, Expr>) -> MyResult<()> {
+        let (name, indent, inner) = self.bind_call(tag, bindings).map_err(|e| located(tag, e))?;
+        let definition = &self.macros[name];
+        let (body, symbols, origin) = (definition.tokens.clone(), definition.symbols.clone(), definition.origin.clone());
+        let outer = std::mem::take(&mut self.tokens);
+        self.calls.push(name.to_string());
+        let expanded = self.expand(&body, &symbols, &inner);
+        self.calls.pop();
+        let expanded_tokens = std::mem::replace(&mut self.tokens, outer);
+        expanded.map_err(|e| simple_error!("{}\n  in macro {}, defined in {}", e, name, origin.display()))?;
+        splice(&mut self.tokens, expanded_tokens, (indent || self.indent_all).then_some(&tag.indent));
+        res_ok(())
+    }
+    /// The macro `tag` calls, whether to indent its body and its parameters
+    /// bound to the arguments
+    fn bind_call<'t>(&mut self, tag: &'t Tag, bindings: &HashMap<String, Expr>)
+        -> MyResult<(&'t str, bool, HashMap<String, Expr>)>
+    {
+        let (name, args, rest) = parse_call(&tag.args, "call")?;
+        let indent = match rest {
+            "" => false,
+            "indent" => true,
+            _ => return res_err(simple_error!("Unexpected {:?} after the arguments of call {}", rest, name)),
+        };
+        let definition = self.macros.get(name).ok_or_else(|| simple_error!("Unknown macro {}", name))?;
+        if args.len() != definition.params.len() {
+            return res_err(simple_error!(
+                "Macro {} takes {} arguments, got {}",
+                name,
+                definition.params.len(),
+                args.len()
+            ));
+        }
+        if self.calls.iter().any(|call| call == name) {
+            return res_err(simple_error!("Macro cycle: {} -> {}", self.calls.join(" -> "), name));
+        }
+        if self.calls.len() >= MAX_CALL_DEPTH {
+            return res_err(simple_error!("Macro calls nest more than {} levels deep", MAX_CALL_DEPTH));
+        }
+        let params = definition.params.clone();
+        let mut inner = HashMap::new();
+        for (param, src) in params.into_iter().zip(args) {
+            // names of the caller, bound in turn if it is a macro too
+            inner.insert(param, bind_expr(src, bindings, &mut self.symbols)?);
+        }
+        res_ok((name, indent, inner))
+    }
+}
+
+/// The call `tag` of an inlined template, its arguments bound like [rebind]
+/// does, written back over the output's `symbols`
+pub(crate) fn rebind_call(tag: &Tag, bindings: &HashMap<String, Expr>, symbols: &mut Vec<String>) -> MyResult<Tag> {
+    let (name, args, rest) = parse_call(&tag.args, "call").map_err(|e| located(tag, e))?;
+    let args = args.into_iter()
+        .map(|src| bind_expr(src, bindings, symbols)?.to_source(symbols))
+        .collect::<MyResult<Vec<_>>>()?;
+    let args = match rest {
+        "" => format!("{}({})", name, args.join(", ")),
+        rest => format!("{}({}) {}", name, args.join(", "), rest),
+    };
+    res_ok(Tag { args, ..tag.clone() })
+}
+
+/// `error` of `tag`, as a [Diagnostic] at the tag if it was parsed from a
+/// template, in the file it came from if known
+fn located(tag: &Tag, error: AnyErr) -> AnyErr {
+    match (tag.span, &tag.origin) {
+        (Some(span), Some(origin)) => Diagnostic::at(span, error.to_string()).in_file(origin).into(),
+        (Some(span), None) => Diagnostic::at(span, error.to_string()).into(),
+        (None, _) => error,
+    }
+}
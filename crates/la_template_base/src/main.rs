@@ -1,11 +1,11 @@
 use clap::Parser;
-use la_template_base::{generate_template};
+use la_template_base::{parse_template_recovering, BatchGenerate, Builtins, ConcreteTemplate, EscapeMode, Escapes, GenerateOptions, GenerateTemplate, IncludePath, ParseOptions, Preprocessor};
 use common::AnyErr;
 use serde_json::Value;
 use std::{
     fs::File,
-    io::{BufReader, Write},
-    path::PathBuf,
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
 };
 
 #[derive(Debug, Parser)]
@@ -20,23 +20,177 @@ struct Args {
     template: PathBuf,
     /// The path to a JSON file that lists at least all
     /// of the variables declared in template.
+    /// Optional with `--directives`, where its top-level scalar values
+    /// become macros.
     #[clap(short, long, value_parser)]
-    var_json: PathBuf,
+    var_json: Option<PathBuf>,
+    /// How substituted values are escaped: none, json, yaml, shell, xml or url.
+    /// Defaults to what the template's extension suggests, e.g. `*.t.json`.
+    #[clap(short, long, value_parser)]
+    escape: Option<EscapeMode>,
+    /// Treat the template as C-preprocessor-style directives
+    /// (`#define`, `#ifdef`, `#include`, ...) instead of `${...}` substitutions.
+    #[clap(long)]
+    directives: bool,
+    /// Substitute only the variables `--var-json` defines and print the rest
+    /// back as a template, for rendering in stages.
+    #[clap(long)]
+    partial: bool,
+    /// Expand `${...}` references inside variable definitions, up to this many
+    /// levels deep. `0`, the default, substitutes definitions as they are.
+    #[clap(long, value_parser, default_value_t = 0)]
+    expand_depth: usize,
+    /// Fail on variables in `--var-json` that the template never uses.
+    #[clap(long)]
+    strict: bool,
+    /// Accept literal text that is not UTF-8 (Latin-1, binary blobs) and copy it
+    /// through unchanged. Substituted values are still UTF-8.
+    #[clap(long)]
+    binary: bool,
+    /// Escape sequences of literal text: sigil (`\$` and `\\`), c (also `\n`, `\t`, `\r`, `\0`) or off
+    #[clap(long, value_parser, default_value = "sigil")]
+    escapes: Escapes,
+    /// Macro definition for `--directives`: NAME, NAME=VALUE or NAME(ARGS)=BODY
+    #[clap(short = 'D', long = "define", value_parser)]
+    defines: Vec<String>,
+    /// Directory searched by `${% include %}` and `${% import %}`, or by `#include` with `--directives`
+    #[clap(short = 'I', long = "include-dir", value_parser)]
+    include_dirs: Vec<PathBuf>,
+    /// Alias for `${% include "@ALIAS/..." %}` and `${% import %}`: ALIAS=DIR
+    #[clap(long = "include-alias", value_parser)]
+    include_aliases: Vec<String>,
+    /// Read one JSON object of variables per line of stdin (JSON Lines)
+    /// and render the template once for each.
+    #[clap(long)]
+    jsonl: bool,
+    /// With `--jsonl`, write each output to this path, `{}` standing for the
+    /// record's line number. Outputs go to stdout otherwise.
+    #[clap(short, long, value_parser)]
+    output: Option<String>,
 }
 
 fn main_result() -> Result<(), AnyErr> {
     let args = Args::parse();
-    let template_f = File::open(args.template)?;
-    let var_f = File::open(args.var_json)?;
-    let template_bf = BufReader::new(template_f);
-    let vars: Value = serde_json::from_reader(BufReader::new(var_f))?;
-    let output = generate_template(template_bf, vars)?;
+    if args.jsonl {
+        return batch(&args);
+    }
+    let vars: Option<Value> = match &args.var_json {
+        Some(path) => Some(serde_json::from_reader(BufReader::new(File::open(path)?))?),
+        None => None,
+    };
+    let output = if args.directives {
+        directives(&args, vars)?.into_bytes()
+    } else {
+        substitutions(&args, vars.ok_or("--var-json is required without --directives")?)?
+    };
     std::io::stdout()
-        .write_all(output.as_bytes())
+        .write_all(&output)
         .map_err(|err| err.into())
 }
 
+fn substitutions(args: &Args, vars: Value) -> Result<Vec<u8>, AnyErr> {
+    let escape = args.escape.or_else(|| EscapeMode::from_path(&args.template));
+    let generate = GenerateTemplate {
+        template: &read_template(args)?.into(),
+        variables: &vars.into(),
+        options: GenerateOptions {
+            escape,
+            max_expand_depth: args.expand_depth,
+            strict: args.strict,
+            builtins: Builtins::default().with_template_path(&args.template),
+        },
+    };
+    if args.partial {
+        generate.partial_text().map(String::into_bytes)
+    } else {
+        generate.generate_bytes()
+    }
+}
+
+/// Parses `--template`, resolving the templates it extends, includes and imports
+fn read_template(args: &Args) -> Result<ConcreteTemplate, AnyErr> {
+    let options = ParseOptions { binary: args.binary, escapes: args.escapes, ..Default::default() };
+    // every syntax error of a file at once, located as `path:line:column`
+    let parse = |path: &Path| {
+        let (template, diagnostics) = parse_template_recovering(BufReader::new(File::open(path)?), options)?;
+        match diagnostics.is_empty() {
+            true => Ok(template),
+            false => Err(diagnostics.into_iter().map(|d| d.in_file(path).to_string()).collect::<Vec<_>>().join("\n").into()),
+        }
+    };
+    let aliases = args.include_aliases
+        .iter()
+        .map(|alias| alias.split_once('=').ok_or_else(|| format!("Expected ALIAS=DIR, got {:?}", alias)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let search = args.include_dirs
+        .iter()
+        .fold(IncludePath::new(), IncludePath::with_dir)
+        .with_aliases(aliases);
+    parse(&args.template)?
+        .resolve_inheritance(&args.template, parse)?
+        .resolve_includes(&args.template, &search, parse)?
+        .resolve_macros(&args.template, &search, parse)
+}
+
+fn batch(args: &Args) -> Result<(), AnyErr> {
+    let escape = args.escape
+        .or_else(|| args.output.as_ref().and_then(EscapeMode::from_path))
+        .or_else(|| EscapeMode::from_path(&args.template));
+    let template = read_template(args)?.into();
+    let options = GenerateOptions {
+        escape,
+        max_expand_depth: args.expand_depth,
+        strict: args.strict,
+        builtins: Builtins::default().with_template_path(&args.template),
+    };
+    let batch = BatchGenerate::new(&template, options)?;
+    let mut stdout = std::io::stdout().lock();
+    let mut errs = Vec::new();
+    for (idx, line) in std::io::stdin().lock().lines().enumerate() {
+        let (lineno, line) = (idx + 1, line?);
+        if line.trim().is_empty() {
+            continue;
+        }
+        let written = serde_json::from_str::<Value>(&line)
+            .map_err(AnyErr::from)
+            .and_then(|vars| batch.generate_bytes(&vars.into()))
+            .and_then(|output| match &args.output {
+                Some(pattern) => std::fs::write(pattern.replace("{}", &lineno.to_string()), output).map_err(AnyErr::from),
+                None => stdout.write_all(&output).map_err(AnyErr::from),
+            });
+        if let Err(err) = written {
+            errs.push(format!("line {}: {}", lineno, err));
+        }
+    }
+    if errs.is_empty() {
+        Ok(())
+    } else {
+        Err(errs.join("\n").into())
+    }
+}
+
+fn directives(args: &Args, vars: Option<Value>) -> Result<String, AnyErr> {
+    let mut preprocessor = Preprocessor::new();
+    let vars = vars.as_ref().and_then(Value::as_object).into_iter().flatten();
+    for (name, value) in vars {
+        match value {
+            Value::String(s) => preprocessor.define_object(name.as_str(), s),
+            Value::Number(_) | Value::Bool(_) => preprocessor.define_object(name.clone(), value.to_string()),
+            _ => continue,
+        };
+    }
+    for definition in &args.defines {
+        preprocessor.define(definition)?;
+    }
+    for dir in &args.include_dirs {
+        preprocessor.include_dir(dir);
+    }
+    preprocessor.process_file(&args.template)
+}
+
 fn main() {
-    main_result()
-        .unwrap_or_else(|err| eprintln!("Failed to parse given templates: {}", err))
+    if let Err(err) = main_result() {
+        eprintln!("Failed to generate from given templates: {}", err);
+        std::process::exit(1);
+    }
 }
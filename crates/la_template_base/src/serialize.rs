@@ -0,0 +1,323 @@
+//! Variables taken from any [Serialize] value, e.g. a typed config struct.
+//! Nested fields are flattened into dotted names (`db.host`, `hosts.0`) by a
+//! [Serializer] that writes straight into the map, without going through JSON.
+
+use std::{collections::HashMap, fmt};
+
+use common::{res_ok, MyResult};
+use serde::{ser, Serialize, Serializer};
+
+use crate::VariableMap;
+
+impl VariableMap {
+    /// Variables of a struct or map, see [flatten_vars]
+    pub fn from_serialize<T: Serialize + ?Sized>(value: &T) -> MyResult<Self> {
+        flatten_vars(value).map(Self::HashMapStd)
+    }
+}
+
+/// Flattens the fields of `value` into variables. Nested structs, maps and
+/// sequences are joined with `.`, `None` and `()` leave the variable undefined,
+/// unit enum variants become their name. An empty sequence or map has no
+/// elements to name, so it defines no variable either.
+///
+/// Only the leaves are kept: a struct, map or sequence is not a variable of
+/// its own, so `to_yaml` and `to_json` cannot render it. Convert the value
+/// with `serde_json::to_value` instead when the template needs them.
+pub fn flatten_vars<T: Serialize + ?Sized>(value: &T) -> MyResult<HashMap<String, String>> {
+    let mut vars = HashMap::new();
+    value.serialize(Flatten { vars: &mut vars, prefix: None })?;
+    res_ok(vars)
+}
+
+#[derive(Debug)]
+struct FlattenError(String);
+
+impl fmt::Display for FlattenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for FlattenError {}
+
+impl ser::Error for FlattenError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self(msg.to_string())
+    }
+}
+
+/// Writes one value, named `prefix`, into `vars`
+struct Flatten<'v> {
+    vars: &'v mut HashMap<String, String>,
+    /// None at the top level, which must be a struct or map
+    prefix: Option<String>,
+}
+
+impl<'v> Flatten<'v> {
+    fn leaf(self, value: String) -> Result<(), FlattenError> {
+        let name = self.prefix
+            .ok_or_else(|| FlattenError(format!("Variables must be a struct or map, got {:?}", value)))?;
+        self.vars.insert(name, value);
+        Ok(())
+    }
+    fn compound(self) -> Compound<'v> {
+        Compound { vars: self.vars, prefix: self.prefix, idx: 0, key: None }
+    }
+    /// A sequence or tuple, whose elements need a name to be joined to
+    fn elements(self, kind: &str) -> Result<Compound<'v>, FlattenError> {
+        match self.prefix {
+            Some(_) => Ok(self.compound()),
+            None => Err(FlattenError(format!("Variables must be a struct or map, got a {}", kind))),
+        }
+    }
+}
+
+/// `prefix.name`, or `name` at the top level
+fn join(prefix: &Option<String>, name: &str) -> String {
+    match prefix {
+        Some(prefix) => format!("{}.{}", prefix, name),
+        None => name.to_string(),
+    }
+}
+
+/// Name of a map key, which must be a scalar
+fn key_name<K: Serialize + ?Sized>(key: &K) -> Result<String, FlattenError> {
+    let mut vars = HashMap::new();
+    key.serialize(Flatten { vars: &mut vars, prefix: Some(String::new()) })?;
+    match vars.remove("") {
+        Some(name) if vars.is_empty() => Ok(name),
+        _ => Err(FlattenError("Map keys must be strings, numbers, chars or bools".to_string())),
+    }
+}
+
+impl<'v> Serializer for Flatten<'v> {
+    type Ok = ();
+    type Error = FlattenError;
+    type SerializeSeq = Compound<'v>;
+    type SerializeTuple = Compound<'v>;
+    type SerializeTupleStruct = Compound<'v>;
+    type SerializeTupleVariant = Compound<'v>;
+    type SerializeMap = Compound<'v>;
+    type SerializeStruct = Compound<'v>;
+    type SerializeStructVariant = Compound<'v>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), FlattenError> {
+        self.leaf(v.to_string())
+    }
+    fn serialize_i8(self, v: i8) -> Result<(), FlattenError> {
+        self.leaf(v.to_string())
+    }
+    fn serialize_i16(self, v: i16) -> Result<(), FlattenError> {
+        self.leaf(v.to_string())
+    }
+    fn serialize_i32(self, v: i32) -> Result<(), FlattenError> {
+        self.leaf(v.to_string())
+    }
+    fn serialize_i64(self, v: i64) -> Result<(), FlattenError> {
+        self.leaf(v.to_string())
+    }
+    fn serialize_i128(self, v: i128) -> Result<(), FlattenError> {
+        self.leaf(v.to_string())
+    }
+    fn serialize_u8(self, v: u8) -> Result<(), FlattenError> {
+        self.leaf(v.to_string())
+    }
+    fn serialize_u16(self, v: u16) -> Result<(), FlattenError> {
+        self.leaf(v.to_string())
+    }
+    fn serialize_u32(self, v: u32) -> Result<(), FlattenError> {
+        self.leaf(v.to_string())
+    }
+    fn serialize_u64(self, v: u64) -> Result<(), FlattenError> {
+        self.leaf(v.to_string())
+    }
+    fn serialize_u128(self, v: u128) -> Result<(), FlattenError> {
+        self.leaf(v.to_string())
+    }
+    fn serialize_f32(self, v: f32) -> Result<(), FlattenError> {
+        self.leaf(v.to_string())
+    }
+    fn serialize_f64(self, v: f64) -> Result<(), FlattenError> {
+        self.leaf(v.to_string())
+    }
+    fn serialize_char(self, v: char) -> Result<(), FlattenError> {
+        self.leaf(v.to_string())
+    }
+    fn serialize_str(self, v: &str) -> Result<(), FlattenError> {
+        self.leaf(v.to_string())
+    }
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), FlattenError> {
+        let value = std::str::from_utf8(v)
+            .map_err(|e| FlattenError(format!("Bytes of {} are not UTF-8: {}", self.prefix.as_deref().unwrap_or("variables"), e)))?;
+        self.leaf(value.to_string())
+    }
+    fn serialize_none(self) -> Result<(), FlattenError> {
+        Ok(())
+    }
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), FlattenError> {
+        value.serialize(self)
+    }
+    fn serialize_unit(self) -> Result<(), FlattenError> {
+        Ok(())
+    }
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), FlattenError> {
+        Ok(())
+    }
+    fn serialize_unit_variant(self, _name: &'static str, _idx: u32, variant: &'static str) -> Result<(), FlattenError> {
+        self.leaf(variant.to_string())
+    }
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<(), FlattenError> {
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _idx: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), FlattenError> {
+        let prefix = Some(join(&self.prefix, variant));
+        value.serialize(Flatten { vars: self.vars, prefix })
+    }
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Compound<'v>, FlattenError> {
+        self.elements("sequence")
+    }
+    fn serialize_tuple(self, _len: usize) -> Result<Compound<'v>, FlattenError> {
+        self.elements("tuple")
+    }
+    fn serialize_tuple_struct(self, name: &'static str, _len: usize) -> Result<Compound<'v>, FlattenError> {
+        self.elements(name)
+    }
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _idx: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'v>, FlattenError> {
+        let prefix = Some(join(&self.prefix, variant));
+        Ok(Flatten { vars: self.vars, prefix }.compound())
+    }
+    fn serialize_map(self, _len: Option<usize>) -> Result<Compound<'v>, FlattenError> {
+        Ok(self.compound())
+    }
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Compound<'v>, FlattenError> {
+        Ok(self.compound())
+    }
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _idx: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> Result<Compound<'v>, FlattenError> {
+        let prefix = Some(join(&self.prefix, variant));
+        Ok(Flatten { vars: self.vars, prefix }.compound())
+    }
+}
+
+/// The elements of a sequence, named by index, or the entries of a map or struct
+struct Compound<'v> {
+    vars: &'v mut HashMap<String, String>,
+    prefix: Option<String>,
+    /// Index of the next sequence element
+    idx: usize,
+    /// Name of the map entry whose value comes next
+    key: Option<String>,
+}
+
+impl<'v> Compound<'v> {
+    fn entry<T: Serialize + ?Sized>(&mut self, name: &str, value: &T) -> Result<(), FlattenError> {
+        let prefix = Some(join(&self.prefix, name));
+        value.serialize(Flatten { vars: self.vars, prefix })
+    }
+    fn element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), FlattenError> {
+        let idx = self.idx.to_string();
+        self.idx += 1;
+        self.entry(&idx, value)
+    }
+}
+
+impl<'v> ser::SerializeSeq for Compound<'v> {
+    type Ok = ();
+    type Error = FlattenError;
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), FlattenError> {
+        self.element(value)
+    }
+    fn end(self) -> Result<(), FlattenError> {
+        Ok(())
+    }
+}
+
+impl<'v> ser::SerializeTuple for Compound<'v> {
+    type Ok = ();
+    type Error = FlattenError;
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), FlattenError> {
+        self.element(value)
+    }
+    fn end(self) -> Result<(), FlattenError> {
+        Ok(())
+    }
+}
+
+impl<'v> ser::SerializeTupleStruct for Compound<'v> {
+    type Ok = ();
+    type Error = FlattenError;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), FlattenError> {
+        self.element(value)
+    }
+    fn end(self) -> Result<(), FlattenError> {
+        Ok(())
+    }
+}
+
+impl<'v> ser::SerializeTupleVariant for Compound<'v> {
+    type Ok = ();
+    type Error = FlattenError;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), FlattenError> {
+        self.element(value)
+    }
+    fn end(self) -> Result<(), FlattenError> {
+        Ok(())
+    }
+}
+
+impl<'v> ser::SerializeMap for Compound<'v> {
+    type Ok = ();
+    type Error = FlattenError;
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), FlattenError> {
+        self.key = Some(key_name(key)?);
+        Ok(())
+    }
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), FlattenError> {
+        let key = self.key.take()
+            .ok_or_else(|| FlattenError("Map value serialized before its key".to_string()))?;
+        self.entry(&key, value)
+    }
+    fn end(self) -> Result<(), FlattenError> {
+        Ok(())
+    }
+}
+
+impl<'v> ser::SerializeStruct for Compound<'v> {
+    type Ok = ();
+    type Error = FlattenError;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), FlattenError> {
+        self.entry(key, value)
+    }
+    fn end(self) -> Result<(), FlattenError> {
+        Ok(())
+    }
+}
+
+impl<'v> ser::SerializeStructVariant for Compound<'v> {
+    type Ok = ();
+    type Error = FlattenError;
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), FlattenError> {
+        self.entry(key, value)
+    }
+    fn end(self) -> Result<(), FlattenError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,86 @@
+//! Checks for [crate::GenerateOptions::strict]: variables the template never uses,
+//! with "did you mean" suggestions for likely typos
+
+use std::io::Cursor;
+
+use itertools::Itertools;
+
+use crate::{expand::{references, Piece}, parse_template, TemplateTrait, VariableMap, VariableTrait};
+
+/// Names defined by `variables` that are neither in `symbols` nor referenced
+/// by a definition expanded from them, sorted; none when `variables`
+/// cannot list its names
+pub(crate) fn unused_vars<'v, S: AsRef<str>>(symbols: &[S], variables: &'v VariableMap, max_expand_depth: usize) -> Vec<&'v str> {
+    let Some(names) = variables.names() else { return Vec::new() };
+    let mut used = symbols.iter().map(|s| s.as_ref().to_string()).collect::<Vec<_>>();
+    let mut idx = 0;
+    while max_expand_depth > 0 && idx < used.len() {
+        let defn = variables.get_defn(&used[idx]);
+        idx += 1;
+        // broken definitions are reported when rendering
+        let Ok(defn) = defn else { continue };
+        for piece in references(&defn) {
+            let Piece::Reference(src) = piece else { continue };
+            let Ok(template) = parse_template(Cursor::new(src.as_bytes())) else { continue };
+            for symbol in template.symbols() {
+                if !used.contains(symbol) {
+                    used.push(symbol.clone());
+                }
+            }
+        }
+    }
+    let mut unused = names.into_iter()
+        .filter(|name| !used.iter().any(|u| u == name))
+        .collect::<Vec<_>>();
+    unused.sort_unstable();
+    unused
+}
+
+/// Describes `unused` variable names, suggesting the closest of `symbols`
+/// for each, e.g. `world_nmae (did you mean world_name?)`
+pub(crate) fn describe_unused<S: AsRef<str>>(unused: &[&str], symbols: &[S]) -> String {
+    unused.iter()
+        .map(|name| match suggest(name, symbols) {
+            Some(symbol) => format!("{} (did you mean {}?)", name, symbol),
+            None => name.to_string(),
+        })
+        .join(", ")
+}
+
+/// The symbol closest to `name`, if it is close enough to be a typo:
+/// at most one edit per three characters, and at least one
+fn suggest<'s, S: AsRef<str>>(name: &str, symbols: &'s [S]) -> Option<&'s str> {
+    let max_distance = (name.chars().count() / 3).max(1);
+    symbols.iter()
+        .map(|symbol| (edit_distance(name, symbol.as_ref()), symbol.as_ref()))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, symbol)| symbol)
+}
+
+/// Levenshtein distance, with a swap of adjacent characters counting as one
+/// edit so that `nmae` is as close to `name` as `nme`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b) = (a.chars().collect::<Vec<_>>(), b.chars().collect::<Vec<_>>());
+    // rows[i][j]: distance between a[..i] and b[..j]
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = (a[i - 1] != b[j - 1]) as usize;
+            let mut distance = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = distance;
+        }
+    }
+    rows[a.len()][b.len()]
+}
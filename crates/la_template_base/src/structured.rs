@@ -0,0 +1,123 @@
+//! Structured values written in the output's own syntax, e.g. a list of
+//! hosts as a YAML sequence with `${hosts | to_yaml}`.
+//!
+//! Values reach filters as text, along with the JSON value behind it when
+//! there is one: arrays and objects of a JSON variable map read as compact
+//! JSON, so the filters read that text back, while scalars keep their type.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{escape::yaml_quote, whitespace::indent_lines};
+
+/// Where a [crate::Filter::ToYaml] or [crate::Filter::ToJson] value is
+/// inserted, filled in by the parser
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Layout {
+    /// Leading whitespace of the line the substitution is on
+    pub indent: String,
+    /// Whether text precedes the substitution on its line, e.g. a YAML key
+    pub after_text: bool,
+}
+
+/// The value `text` stands for, given the JSON value it was read from if
+/// any. Only text of an array or object is read back as JSON, the text of
+/// anything else is a string, e.g. `"1.10"` stays one rather than 1.1.
+pub(crate) fn structured(text: &str, original: Option<&Value>) -> Value {
+    let is_collection = match original {
+        Some(Value::Array(_) | Value::Object(_)) => true,
+        Some(Value::Number(_) | Value::Bool(_) | Value::Null) => return original.cloned().unwrap_or_default(),
+        Some(Value::String(_)) => false,
+        None => text.trim_start().starts_with(['[', '{']),
+    };
+    match is_collection {
+        true => serde_json::from_str(text).unwrap_or_else(|_| Value::String(text.to_string())),
+        false => Value::String(text.to_string()),
+    }
+}
+
+/// `value` as YAML. Collections are written in block style, starting on
+/// the next line when text precedes them, their lines indented to fit in.
+pub(crate) fn to_yaml(value: &Value, layout: &Layout) -> String {
+    let mut lines = Vec::new();
+    match value {
+        Value::Array(items) if !items.is_empty() => yaml_block(value, &mut lines),
+        Value::Object(entries) if !entries.is_empty() => yaml_block(value, &mut lines),
+        scalar => return yaml_inline(scalar),
+    }
+    let text = lines.join("\n");
+    if layout.after_text {
+        let indent = format!("{}  ", layout.indent);
+        format!("\n{}{}", indent, text.replace('\n', &format!("\n{}", indent)))
+    } else {
+        String::from_utf8(indent_lines(text.as_bytes(), &layout.indent, false)).expect("Lines split on ASCII newlines")
+    }
+}
+
+/// `value` as pretty JSON, its lines indented like the line it starts on
+pub(crate) fn to_json(value: &Value, layout: &Layout) -> String {
+    let text = serde_json::to_string_pretty(value).expect("Serializing a Value cannot fail");
+    String::from_utf8(indent_lines(text.as_bytes(), &layout.indent, false)).expect("Lines split on ASCII newlines")
+}
+
+/// Lines of a non-empty collection, unindented
+fn yaml_block(value: &Value, lines: &mut Vec<String>) {
+    match value {
+        Value::Array(items) => items.iter().for_each(|item| yaml_entry("-".to_string(), item, lines)),
+        Value::Object(entries) => entries.iter()
+            .for_each(|(key, value)| yaml_entry(format!("{}:", yaml_string(key)), value, lines)),
+        scalar => lines.push(yaml_inline(scalar)),
+    }
+}
+
+/// A sequence item (`prefix` is `-`) or a mapping entry (`key:`)
+fn yaml_entry(prefix: String, value: &Value, lines: &mut Vec<String>) {
+    if is_empty_or_scalar(value) {
+        lines.push(format!("{} {}", prefix, yaml_inline(value)));
+        return;
+    }
+    let mut inner = Vec::new();
+    yaml_block(value, &mut inner);
+    if prefix == "-" {
+        // the item starts on the dash's line
+        lines.push(format!("- {}", inner[0]));
+    } else {
+        lines.push(prefix);
+        lines.push(format!("  {}", inner[0]));
+    }
+    lines.extend(inner[1..].iter().map(|line| format!("  {}", line)));
+}
+
+fn is_empty_or_scalar(value: &Value) -> bool {
+    match value {
+        Value::Array(items) => items.is_empty(),
+        Value::Object(entries) => entries.is_empty(),
+        _ => true,
+    }
+}
+
+/// A scalar or an empty collection on one line
+fn yaml_inline(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::String(s) => yaml_string(s),
+        Value::Array(_) => "[]".to_string(),
+        Value::Object(_) => "{}".to_string(),
+        number_or_bool => number_or_bool.to_string(),
+    }
+}
+
+/// `s` plain when YAML reads it back as that string, quoted otherwise
+fn yaml_string(s: &str) -> String {
+    const RESERVED: [&str; 10] = ["~", "null", "true", "false", "yes", "no", "on", "off", "y", "n"];
+    let is_plain = s.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_' || c == '/')
+        && s.chars().all(|c| c.is_alphanumeric() || " _-./@".contains(c))
+        && !s.ends_with(' ')
+        && !RESERVED.contains(&s.to_ascii_lowercase().as_str())
+        && s.parse::<f64>().is_err();
+    if is_plain {
+        s.to_string()
+    } else {
+        yaml_quote(s)
+    }
+}
@@ -0,0 +1,133 @@
+//! Whitespace control around `${...}` constructs.
+//!
+//! - Trim markers: `${- x}` removes all whitespace (newlines included)
+//!   before the construct, `${x -}` all whitespace after it.
+//!   Block tags spell them `${%- tag -%}`.
+//! - Standalone lines: a line holding nothing but one block tag or
+//!   comment and spaces/tabs is removed entirely, newline included.
+//! - Indentation: `${x | indent}` aligns the lines of a multi-line value
+//!   with its first, at the column it is output at, see [crate::Filter::Indent].
+
+use std::collections::BTreeSet;
+
+use crate::{BorrowedToken as Token, Layout};
+
+/// Splits the trim markers off the content of `${...}`, returning
+/// `(trim_before, content, trim_after)`. Only a `-` right at either end is
+/// a marker, so `${-x}` trims while `${ -x}` negates `x`.
+pub(crate) fn strip_trim_markers(content: &str) -> (bool, &str, bool) {
+    let (trim_before, content) = match content.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, content),
+    };
+    let (trim_after, content) = match content.strip_suffix('-') {
+        Some(rest) => (true, rest),
+        None => (false, content),
+    };
+    (trim_before, content, trim_after)
+}
+
+fn is_inline_space(c: char) -> bool {
+    c == ' ' || c == '\t'
+}
+
+/// Removes the lines that only hold a block tag or a comment, dropping the
+/// literals that end up empty.
+///
+/// `comments[i]` is the position in `tokens` a comment was dropped at.
+/// Which lines stand alone is decided on the untouched token stream, so
+/// adjacent standalone lines are all removed.
+pub(crate) fn trim_standalone(tokens: &mut Vec<Token<'_>>, comments: &[usize]) {
+    // Every block construct as (index before it, index after it)
+    let tags = (0..tokens.len())
+        .filter(|&idx| matches!(tokens[idx], Token::Tag(_)))
+        .map(|idx| (idx.checked_sub(1), idx + 1));
+    let comments = comments.iter().map(|&pos| (pos.checked_sub(1), pos));
+    let starts_line = |prev: Option<usize>| match prev.map(|prev| (prev, &tokens[prev])) {
+        None => true,
+        Some((prev, Token::Str(s))) => {
+            let indent = s.trim_end_matches(is_inline_space);
+            indent.ends_with('\n') || (indent.is_empty() && prev == 0)
+        }
+        Some(_) => false,
+    };
+    let ends_line = |next: usize| match tokens.get(next) {
+        None => true,
+        Some(Token::Str(s)) => {
+            let rest = s.trim_start_matches(is_inline_space);
+            rest.starts_with('\n') || rest.starts_with("\r\n") || (rest.is_empty() && next + 1 == tokens.len())
+        }
+        Some(_) => false,
+    };
+    let (mut strip_end, mut strip_start) = (BTreeSet::new(), BTreeSet::new());
+    for (prev, next) in tags.chain(comments) {
+        if starts_line(prev) && ends_line(next) {
+            strip_end.extend(prev);
+            strip_start.insert(next);
+        }
+    }
+    for idx in strip_end {
+        if let Some(Token::Str(prev)) = tokens.get_mut(idx) {
+            *prev = prev.trim_end_matches(is_inline_space);
+        }
+    }
+    for idx in strip_start {
+        if let Some(Token::Str(next)) = tokens.get_mut(idx) {
+            let rest = next.trim_start_matches(is_inline_space);
+            let rest = rest.strip_prefix('\n')
+                .or_else(|| rest.strip_prefix("\r\n"))
+                .unwrap_or(rest);
+            *next = rest;
+        }
+    }
+    tokens.retain(|tok| !matches!(tok, Token::Str("")));
+}
+
+/// Text before `at` on its line, with everything but tabs blanked to spaces
+pub(crate) fn indent_at(src: &[u8], at: usize) -> String {
+    let line_start = memchr::memrchr(b'\n', &src[..at]).map_or(0, |nl| nl + 1);
+    String::from_utf8_lossy(&src[line_start..at])
+        .chars()
+        .map(|c| if c == '\t' { c } else { ' ' })
+        .collect()
+}
+
+/// The line `at` is on, as far as [Layout] tells
+pub(crate) fn layout_at(src: &[u8], at: usize) -> Layout {
+    let line_start = memchr::memrchr(b'\n', &src[..at]).map_or(0, |nl| nl + 1);
+    let prefix = String::from_utf8_lossy(&src[line_start..at]);
+    let text_at = prefix.find(|c| c != ' ' && c != '\t');
+    Layout {
+        indent: prefix[..text_at.unwrap_or(prefix.len())].to_string(),
+        after_text: text_at.is_some(),
+    }
+}
+
+/// Keeps `line` the output after its last newline once `piece` is written
+pub(crate) fn track_line(line: &mut Vec<u8>, piece: &[u8]) {
+    match memchr::memrchr(b'\n', piece) {
+        Some(nl) => {
+            line.clear();
+            line.extend_from_slice(&piece[nl + 1..]);
+        }
+        None => line.extend_from_slice(piece),
+    }
+}
+
+/// Inserts `indent` at the start of every line of `text` but the first,
+/// leaving blank lines alone. An empty last line is indented only if more
+/// text `follows` right after.
+pub(crate) fn indent_lines(text: &[u8], indent: &str, follows: bool) -> Vec<u8> {
+    let mut lines = text.split(|b| *b == b'\n').peekable();
+    let mut out = Vec::with_capacity(text.len());
+    out.extend_from_slice(lines.next().unwrap_or_default());
+    while let Some(line) = lines.next() {
+        out.push(b'\n');
+        let is_blank = matches!(line, [] | [b'\r']);
+        if !is_blank || (line.is_empty() && lines.peek().is_none() && follows) {
+            out.extend_from_slice(indent.as_bytes());
+        }
+        out.extend_from_slice(line);
+    }
+    out
+}
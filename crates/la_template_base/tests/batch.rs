@@ -0,0 +1,39 @@
+use la_template_base::*;
+use serde_json::json;
+use std::io::Cursor;
+
+fn template(src: &str) -> Template {
+    BufReadTemplate::new(Cursor::new(src)).expect("Should parse").into()
+}
+
+#[test]
+fn renders_every_record() {
+    let template = template("${host}: \"${port | escape(\"json\")}\"\n");
+    let records = vec![
+        json!({ "host": "a", "port": "1" }),
+        json!({ "host": "b" }),
+        json!({ "host": "c", "port": "3" }),
+    ];
+    let outputs = generate_batch(&template, records).expect("Should plan").collect::<Vec<_>>();
+    assert_eq!(outputs[0].as_ref().expect("Should render"), "a: \"1\"\n");
+    assert!(outputs[1].is_err(), "A missing definition fails only its record");
+    assert_eq!(outputs[2].as_ref().expect("Should render"), "c: \"3\"\n");
+}
+
+#[test]
+fn lazy_over_endless_records() {
+    let template = template("row ${n}");
+    let batch = BatchGenerate::new(&template, GenerateOptions { strict: true, ..Default::default() })
+        .expect("Should plan");
+    let records = (0..).map(|n| json!({ "n": n.to_string() }));
+    assert_eq!(
+        batch.generate_all(records).take(3).collect::<common::MyResult<Vec<_>>>().expect("Should render"),
+        vec!["row 0", "row 1", "row 2"]
+    );
+    assert!(batch.generate(&json!({ "n": "1", "extra": "x" }).into()).is_err());
+}
+
+#[test]
+fn plan_errors_early() {
+    assert!(BatchGenerate::new(&template("${% unknown %}"), Default::default()).is_err());
+}
@@ -0,0 +1,41 @@
+use la_template_base::*;
+use serde_json::json;
+use std::io::Cursor;
+
+#[test]
+fn literals_borrow_from_source() {
+    let src = String::from("a: ${x}\n$# dropped\nb: \\${x} ${% tag %}\n");
+    let template = parse_str(&src).expect("Should parse");
+    let BorrowedToken::Str(first) = template.tokens()[0] else { panic!("Expected a literal first") };
+    assert_eq!(first, "a: ");
+    assert!(src.as_bytes().as_ptr_range().contains(&first.as_ptr()), "Literal should point into the source");
+    assert_eq!(template.symbols(), &["x"]);
+}
+
+#[test]
+fn same_as_owned_parser() {
+    let sources = [
+        "+++\nescape = \"json\"\n+++\n{\"a\": ${a | escape(\"none\")}, \"b\": ${b}}",
+        "- a\n  ${% tag %}  \n- b ${-x-}  c \\$12 ${# note #}\n$# line\n",
+        "héllo ${wörld} — \\$ ${x -}   y",
+    ];
+    for src in sources {
+        assert_eq!(
+            parse_str(src).expect("Should parse").into_owned(),
+            parse_template(Cursor::new(src)).expect("Should parse"),
+        );
+    }
+}
+
+#[test]
+fn renders_without_copying() {
+    let template = parse_str("hello ${name}, \\$${cost}").expect("Should parse");
+    assert_eq!(
+        template.generate(&json!({ "name": "world", "cost": "12" }).into(), Default::default())
+            .expect("Should render"),
+        "hello world, $12"
+    );
+    let large = "x".repeat(1 << 20) + "${name}";
+    let template = parse_borrowed(large.as_bytes(), Default::default()).expect("Should parse");
+    assert_eq!(template.tokens().len(), 2);
+}
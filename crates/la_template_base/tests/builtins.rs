@@ -0,0 +1,70 @@
+use la_template_base::*;
+use serde_json::json;
+use std::io::Cursor;
+use std::sync::{atomic::{AtomicU64, Ordering}, Arc};
+use std::time::{Duration, UNIX_EPOCH};
+
+fn pinned() -> Builtins {
+    Builtins::default()
+        .with_clock(|| UNIX_EPOCH + Duration::from_secs(951_782_400))
+        .with_rng(|| u128::MAX)
+        .with_hostname("build-01")
+        .with_user("ci")
+        .with_template_path("templates/motd.t.txt")
+}
+
+fn render(template: &str, builtins: Builtins) -> common::MyResult<String> {
+    GenerateTemplate {
+        template: &Cursor::new(template).into(),
+        variables: &json!({"owner": "${la.user}@${la.hostname}"}).into(),
+        options: GenerateOptions { builtins, max_expand_depth: DEFAULT_EXPAND_DEPTH, ..Default::default() },
+    }.generate()
+}
+
+#[test]
+fn pinned_builtins() {
+    assert_eq!(
+        render("${la.now} ${la.date} ${la.timestamp} ${la.template_name} by ${owner}", pinned()).expect("Should render"),
+        "2000-02-29T00:00:00Z 2000-02-29 951782400 motd.t.txt by ci@build-01"
+    );
+    assert_eq!(
+        render("${la.uuid}", pinned()).expect("Should render"),
+        "ffffffff-ffff-4fff-bfff-ffffffffffff"
+    );
+}
+
+#[test]
+fn unknown_and_unavailable_builtins() {
+    let err = render("${la.target} ${la.nope}", pinned()).expect_err("Neither is defined");
+    assert_eq!(err.to_string(), "Missing definition: [\"la.target\", \"la.nope\"]");
+    let err = pinned().get("la.nope").expect_err("la.nope is unknown");
+    assert!(err.to_string().starts_with("Unknown built-in variable la.nope"), "{}", err);
+}
+
+#[test]
+fn taken_once_per_render() {
+    // every call of the clock or the RNG gives a new value
+    let ticks = Arc::new(AtomicU64::new(0));
+    let (clock_ticks, rng_ticks) = (ticks.clone(), ticks.clone());
+    let builtins = Builtins::default()
+        .with_clock(move || UNIX_EPOCH + Duration::from_secs(clock_ticks.fetch_add(1, Ordering::SeqCst)))
+        .with_rng(move || rng_ticks.fetch_add(1, Ordering::SeqCst).into());
+    let template = Cursor::new("${la.timestamp} ${la.uuid} ${la.timestamp} ${la.uuid}").into();
+    let variables = json!({}).into();
+    let generate = GenerateTemplate {
+        template: &template,
+        variables: &variables,
+        options: GenerateOptions { builtins, max_expand_depth: DEFAULT_EXPAND_DEPTH, ..Default::default() },
+    };
+    let first = generate.generate().expect("Should render");
+    let words = first.split(' ').collect::<Vec<_>>();
+    assert_eq!((words[0], words[1]), (words[2], words[3]), "{}", first);
+    let second = generate.generate().expect("Should render");
+    assert_ne!(first, second, "Each render takes its own");
+
+    // clones share the clock, not the values of a render
+    let (render, clone) = (generate.options.builtins.begin_render(), generate.options.builtins.clone());
+    let uuid = render.get("la.uuid").expect("Should take a UUID");
+    assert_ne!(clone.begin_render().get("la.uuid").expect("Should take a UUID"), uuid);
+    assert_eq!(render.get("la.uuid").expect("Should keep the UUID"), uuid);
+}
@@ -2,7 +2,8 @@ use la_template_base::*;
 use common::MyResult;
 use serde_json::Value;
 use std::fs::File;
-use std::io::{BufReader, Cursor};
+use std::io::{BufReader, Cursor, Write};
+use std::process::{Command, Stdio};
 use std::sync::Once;
 
 fn wrapped<AnyStr0: AsRef<str>, AnyStr1: AsRef<str>>(
@@ -82,3 +83,24 @@ fn missing_var_def() {
             .expect("Over-defining is non-error");
     assert_eq!(spare, "Many def is good".to_string());
 }
+
+#[test]
+fn failures_exit_nonzero() {
+    let run = |args: &[&str], stdin: &str| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_la_template_base"))
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Should run the CLI");
+        child.stdin.take().expect("Stdin is piped").write_all(stdin.as_bytes()).expect("Should write stdin");
+        child.wait().expect("Should exit").success()
+    };
+    let template = "tests/hello_report.t.txt";
+    assert!(run(&["-t", template, "-v", "tests/hello_report.json"], ""));
+    assert!(!run(&["-t", template, "-v", "tests/no_such_vars.json"], ""));
+    let over_defined = r#"{"world_name": "world", "name": "me", "over": "x"}"#;
+    assert!(run(&["-t", template, "--jsonl"], over_defined));
+    assert!(!run(&["-t", template, "--jsonl", "--strict"], over_defined));
+}
@@ -0,0 +1,71 @@
+use la_template_base::*;
+use serde_json::json;
+use std::io::Cursor;
+
+fn tokens(template: &str) -> Vec<Token> {
+    parse_template(Cursor::new(template))
+        .expect("Should parse")
+        .tokens()
+        .clone()
+}
+
+#[test]
+fn line_comments() {
+    assert_eq!(
+        tokens("- block:\n$# Create a new vm if vm is not yet created\n  - name: ${name}\n"),
+        vec![Token::from("- block:\n  - name: "), Token::Var(0), Token::from("\n")]
+    );
+    // trailing comments keep the line they annotate
+    assert_eq!(
+        tokens("changed_when: true ${# guaranteed change #}\nnext"),
+        vec![Token::from("changed_when: true \nnext")]
+    );
+    assert_eq!(tokens("  $# only a comment"), vec![]);
+}
+
+#[test]
+fn shell_argument_count_is_not_a_comment() {
+    let err = parse_template(Cursor::new("if [ $# -eq 0 ]; then
+  exit 1
+fi
+"))
+        .expect_err("$# after text is not a comment");
+    assert!(err.to_string().contains("must start their line"), "Unexpected error: {err}");
+    assert_eq!(
+        tokens("if [ \\$# -eq 0 ]; then"),
+        vec![Token::from("if [ $# -eq 0 ]; then")]
+    );
+}
+
+#[test]
+fn block_comments() {
+    assert_eq!(
+        tokens("a${# inline } comment #}b"),
+        vec![Token::from("ab")]
+    );
+    assert_eq!(
+        tokens("keep:\n    ${#\n    multi-line\n    #}\nnext: ${x}"),
+        vec![Token::from("keep:\nnext: "), Token::Var(0)]
+    );
+    assert_eq!(
+        tokens("a \n ${#- trimmed -#} \n b"),
+        vec![Token::from("ab")]
+    );
+    assert_eq!(tokens("${##}"), vec![]);
+}
+
+#[test]
+fn comments_never_render() {
+    let template = "$# header\nhello ${name}${# , unused ${other} #}!\n";
+    assert_eq!(
+        generate_template(Cursor::new(template), json!({"name": "world"})).expect("Should render"),
+        "hello world!\n"
+    );
+}
+
+#[test]
+fn unterminated_comment() {
+    assert!(parse_template(Cursor::new("${# never closed }")).is_err());
+    assert!(parse_template(Cursor::new("${#}")).is_err());
+    assert!(parse_template(Cursor::new("$ not a construct")).is_err());
+}
@@ -0,0 +1,25 @@
+//! Helpers shared by the integration tests
+
+use std::{io::Cursor, path::Path};
+
+use ::common::MyResult;
+use la_template_base::{parse_template, ConcreteTemplate};
+
+/// Loads a template by path, see [resolve]
+pub type Load<'f> = dyn Fn(&Path) -> MyResult<ConcreteTemplate> + 'f;
+
+/// Parses `path` from `files`, an in-memory file system of `(path, source)`,
+/// and resolves it with `then`, which loads the templates it refers to from
+/// `files` too
+pub fn resolve<F>(files: &[(&str, &str)], path: &str, then: F) -> MyResult<ConcreteTemplate>
+where
+    F: FnOnce(ConcreteTemplate, &Path, &Load) -> MyResult<ConcreteTemplate>,
+{
+    let load = |path: &Path| {
+        let (_, src) = files.iter()
+            .find(|(name, _)| path.to_str() == Some(*name))
+            .ok_or("No such file")?;
+        parse_template(Cursor::new(*src))
+    };
+    then(load(Path::new(path))?, Path::new(path), &load)
+}
@@ -0,0 +1,81 @@
+use la_template_base::*;
+use std::io::Cursor;
+
+fn process(pre: &mut Preprocessor, template: &str) -> common::MyResult<String> {
+    pre.process(Cursor::new(template), None)
+}
+
+#[test]
+fn object_and_function_macros() {
+    let mut pre = Preprocessor::new();
+    let template = "\
+#define STR(x) _STR(x)
+#define _STR(x) #x
+#define KEY(POST) ~/.ssh/{{ key }}POST
+#define CAT(a, b) a ## b
+#define LIST(...) [__VA_ARGS__]
+path: STR(KEY())
+pub: STR(KEY(.pub))
+CAT(user, _name): LIST(1, 2)
+url: https://example.org // kept, not a directive
+";
+    assert_eq!(
+        process(&mut pre, template).expect("Should preprocess"),
+        "path: \"~/.ssh/{{ key }}\"\npub: \"~/.ssh/{{ key }}.pub\"\nuser_name: [1,2]\nurl: https://example.org // kept, not a directive\n"
+    );
+    // strings, partial words and self references are left alone
+    let mut pre = Preprocessor::new();
+    pre.define("X=X + Y").expect("Should define").define("Y=2").expect("Should define");
+    assert_eq!(process(&mut pre, "X \"X\" XY isn't\n").expect("Should preprocess"), "X + 2 \"X\" XY isn't\n");
+}
+
+#[test]
+fn conditionals() {
+    let template = "\
+#// authoring note
+#ifdef BECOME
+become: BECOME
+#elif defined(USER) && USER > 1
+user: USER
+#else
+plain
+#endif
+#if !defined(BECOME) || 0
+#ifndef USER
+no user
+#endif
+#endif
+";
+    let mut pre = Preprocessor::new();
+    assert_eq!(process(&mut pre, template).expect("Should preprocess"), "plain\nno user\n");
+    pre.define("USER=0x2").expect("Should define");
+    assert_eq!(process(&mut pre, template).expect("Should preprocess"), "user: 0x2\n");
+    pre.define("BECOME=yes").expect("Should define");
+    assert_eq!(process(&mut pre, template).expect("Should preprocess"), "become: yes\n");
+}
+
+#[test]
+fn includes() {
+    let mut pre = Preprocessor::new();
+    assert_eq!(
+        pre.process_file("tests/directive/main.t.cc").expect("Should preprocess"),
+        "hello world\n"
+    );
+    let err = pre.process_file("tests/directive/loop.h").expect_err("Includes itself forever");
+    assert!(err.to_string().contains("loop.h -> tests/directive/loop.h"), "Unexpected error: {err}");
+}
+
+#[test]
+fn errors() {
+    let mut pre = Preprocessor::new();
+    let err = process(&mut pre, "a\n#ifndef COMMAND\n#error COMMAND expected to be defined\n#endif\n")
+        .expect_err("#error should fail");
+    assert_eq!(err.to_string(), "<input>:3: #error COMMAND expected to be defined");
+    assert!(process(&mut pre, "#if 1\n").is_err());
+    assert!(process(&mut pre, "#endif\n").is_err());
+    assert!(process(&mut pre, "#bogus\n").is_err());
+    assert!(process(&mut pre, "#if 1 / 0\n#endif\n").is_err());
+    assert!(process(&mut pre, "#define F(a, b) a b\nF(1)\n").is_err());
+    // directives in skipped groups are not checked
+    assert_eq!(process(&mut pre, "#if 0\n#bogus\n#error no\n#endif\nok\n").expect("Should preprocess"), "ok\n");
+}
@@ -0,0 +1,3 @@
+#include "greeting.h"
+#include "greeting.h"
+GREETING(world)
@@ -0,0 +1,98 @@
+use la_template_base::*;
+use serde_json::{json, Value};
+use std::io::Cursor;
+
+const HOSTILE: &str = "a\"b\\c: d\n'e' <f> & g\u{2028}";
+
+fn render(template: &str, vars: Value, escape: Option<EscapeMode>) -> common::MyResult<String> {
+    GenerateTemplate {
+        template: &BufReadTemplate::new(Cursor::new(template))?.into(),
+        variables: &vars.into(),
+        options: GenerateOptions { escape, ..Default::default() },
+    }.generate()
+}
+
+#[test]
+fn json_stays_valid() {
+    let output = render(r#"{"name": "${name}", "list": ["${name}"], "greeting": "hello ${name}!"}"#, json!({ "name": HOSTILE }), Some(EscapeMode::Json))
+        .expect("Should render");
+    let parsed: Value = serde_json::from_str(&output).expect("Output should be valid JSON");
+    assert_eq!(parsed["name"], HOSTILE);
+    assert_eq!(parsed["list"][0], HOSTILE);
+    assert_eq!(parsed["greeting"], format!("hello {}!", HOSTILE));
+}
+
+#[test]
+fn placeholder_inside_string() {
+    let vars = json!({ "name": "naomi", "port": "8080" });
+    let output = render(r#"{"greeting": "hello ${name}", "port": ${port}}"#, vars.clone(), EscapeMode::from_path("x.json"))
+        .expect("Should render");
+    assert_eq!(output, r#"{"greeting": "hello naomi", "port": 8080}"#);
+    let output = render("msg: \"hello ${name}\"\n", vars, EscapeMode::from_path("x.yml")).expect("Should render");
+    assert_eq!(output, "msg: \"hello naomi\"\n");
+}
+
+#[test]
+fn unquoted_placeholder_gets_quoted() {
+    let vars = json!({ "port": "1, \"admin\": true", "name": "x\nadmin: true" });
+    let output = render(r#"{"port": ${port}, "escaped": "a\"${port}"}"#, vars.clone(), Some(EscapeMode::Json))
+        .expect("Should render");
+    let parsed: Value = serde_json::from_str(&output).expect("Output should be valid JSON");
+    assert_eq!(parsed, json!({ "port": "1, \"admin\": true", "escaped": "a\"1, \"admin\": true" }));
+    let output = render("port: ${port}\nname: ${name}\n", vars, Some(EscapeMode::Yaml)).expect("Should render");
+    assert_eq!(output, "port: \"1, \\\"admin\\\": true\"\nname: \"x\\nadmin: true\"\n");
+}
+
+#[test]
+fn mode_from_path() {
+    assert_eq!(EscapeMode::from_path("out/config.json"), Some(EscapeMode::Json));
+    assert_eq!(EscapeMode::from_path("play.t.yml"), Some(EscapeMode::Yaml));
+    assert_eq!(EscapeMode::from_path("run.sh"), Some(EscapeMode::Shell));
+    assert_eq!(EscapeMode::from_path("index.html"), Some(EscapeMode::Xml));
+    assert_eq!(EscapeMode::from_path("hello_world.txt"), None);
+    assert_eq!(EscapeMode::from_path("Makefile"), None);
+}
+
+#[test]
+fn filter_overrides_default() {
+    let vars = json!({ "q": "a b&c", "file": "it's here" });
+    let output = render(
+        r#"curl "https://x.io/?q=${q | escape("url")}" -o ${file}"#,
+        vars,
+        Some(EscapeMode::Shell),
+    ).expect("Should render");
+    assert_eq!(output, r#"curl "https://x.io/?q=a%20b%26c" -o 'it'\''s here'"#);
+
+    let output = render(r#"${q | escape("none")} ${q | escape(xml)}"#, json!({"q": "<&>"}), Some(EscapeMode::Json))
+        .expect("Should render");
+    assert_eq!(output, "<&> &lt;&amp;&gt;");
+}
+
+#[test]
+fn front_matter_mode() {
+    let template = "+++\nescape = \"yaml\"\n+++\nname: \"${name}\"\n";
+    let output = render(template, json!({ "name": HOSTILE }), None).expect("Should render");
+    assert_eq!(output, "name: \"a\\\"b\\\\c: d\\n'e' <f> & g\\L\"\n");
+
+    // front matter wins over the caller's default, which would write \u2028
+    let output = render(template, json!({ "name": "x\u{2028}" }), Some(EscapeMode::Json)).expect("Should render");
+    assert_eq!(output, "name: \"x\\L\"\n");
+
+    let err = render("+++\nescape = \"yaml\"\nname: ${name}", json!({ "name": "x" }), None)
+        .expect_err("Front matter must be closed");
+    assert!(err.to_string().contains("front matter"), "Unexpected error: {err}");
+}
+
+#[test]
+fn shell_words() {
+    let mode = EscapeMode::Shell;
+    assert_eq!(mode.escape("plain-word_1.txt".into()), "plain-word_1.txt");
+    assert_eq!(mode.escape("".into()), "''");
+    assert_eq!(mode.escape("$(rm -rf /)".into()), "'$(rm -rf /)'");
+}
+
+#[test]
+fn unknown_filter() {
+    assert!(BufReadTemplate::new(Cursor::new("${a | shout}")).is_err());
+    assert!(BufReadTemplate::new(Cursor::new(r#"${a | escape("klingon")}"#)).is_err());
+}
@@ -0,0 +1,57 @@
+use la_template_base::*;
+
+fn lex(src: &str, escapes: Escapes) -> Result<Vec<Token>, String> {
+    parse_borrowed(src.as_bytes(), ParseOptions { escapes, ..Default::default() })
+        .map(|template| template.into_owned().tokens().clone())
+        .map_err(|e| e.to_string())
+}
+
+#[test]
+fn sigil_escapes() {
+    let lex = |src| lex(src, Escapes::Sigil).expect("Should lex");
+    assert_eq!(lex("cost: \\$5"), vec![Token::from("cost: $5")]);
+    // a doubled escape is one backslash, so the sigil after it starts a substitution
+    assert_eq!(lex("C:\\\\${dir}"), vec![Token::from("C:\\"), Token::Var(0)]);
+    assert_eq!(lex("\\\\\\${dir}"), vec![Token::from("\\${dir}")]);
+    // other backslashes are kept, e.g. shell continuations
+    assert_eq!(lex("a \\\n b\\n \\"), vec![Token::from("a \\\n b\\n \\")]);
+    assert_eq!(lex("\\\\\\\\${dir}"), vec![Token::from("\\\\"), Token::Var(0)]);
+}
+
+#[test]
+fn doubled_backslashes_kept() {
+    // backslashes render as written unless a `$` follows them
+    let src = "path: C:\\\\dir\\\\${name}\nshare: \\\\\\\\host\\\\share\n";
+    let template = parse_template(std::io::Cursor::new(src)).expect("Should parse");
+    assert_eq!(
+        generate_template(template, serde_json::json!({ "name": "x" })).expect("Should render"),
+        "path: C:\\\\dir\\x\nshare: \\\\\\\\host\\\\share\n"
+    );
+}
+
+#[test]
+fn c_escapes() {
+    let lex = |src| lex(src, Escapes::C);
+    assert_eq!(lex("a\\tb\\r\\n\\0 \\\\ \\$"), Ok(vec![Token::from("a\tb\r\n\0 \\ $")]));
+    assert_eq!(lex("${x -}  \\n"), Ok(vec![Token::Var(0), Token::from("\n")]));
+    assert_eq!(lex("\\q"), Err("1:1: Unknown escape sequence '\\q'".to_string()));
+    assert_eq!(lex("end \\"), Err("1:5: Escape '\\' at the end of the template".to_string()));
+}
+
+#[test]
+fn escapes_off() {
+    assert_eq!(
+        lex("C:\\\\${dir}\\n", Escapes::Off),
+        Ok(vec![Token::from("C:\\\\"), Token::Var(0), Token::from("\\n")])
+    );
+    assert!(lex("\\${dir}", Escapes::Off).is_ok_and(|tokens| tokens[1] == Token::Var(0)));
+}
+
+#[test]
+fn written_back() {
+    for src in ["C:\\\\${dir}", "\\\\\\$ \\q \\", "${a}\\\\\\\\${b}", "C:\\\\dir \\\\\\$"] {
+        let template = parse_template(std::io::Cursor::new(src)).expect("Should parse");
+        let source = template.to_source().expect("Should write back");
+        assert_eq!(parse_template(std::io::Cursor::new(source)).expect("Should parse"), template);
+    }
+}
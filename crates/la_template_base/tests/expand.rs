@@ -0,0 +1,96 @@
+use la_template_base::*;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::Cursor;
+
+fn render<V: Into<VariableMap>>(template: &str, vars: V) -> common::MyResult<String> {
+    GenerateTemplate {
+        template: &Cursor::new(template).into(),
+        variables: &vars.into(),
+        options: GenerateOptions { max_expand_depth: DEFAULT_EXPAND_DEPTH, ..Default::default() },
+    }.generate()
+}
+
+#[test]
+fn expansion_is_opt_in() {
+    let vars = json!({"host": "example.com", "base_url": "https://${host}/"});
+    assert_eq!(
+        generate_template(Cursor::new("${base_url}"), vars).expect("Should render"),
+        "https://${host}/"
+    );
+}
+
+#[test]
+fn nested_reference() {
+    let vars = json!({
+        "host": "example.com",
+        "port": "8080",
+        "base_url": "https://${host}:${port}",
+        "health": "${base_url}/health"
+    });
+    assert_eq!(
+        render("GET ${health}", vars).expect("Should expand nested references"),
+        "GET https://example.com:8080/health"
+    );
+}
+
+#[test]
+fn hashmap_source() {
+    let vars: HashMap<String, String> = HashMap::from_iter([
+        ("user".to_string(), "naomi".to_string()),
+        ("home".to_string(), "/home/${user}".to_string()),
+        ("price".to_string(), "\\${12}".to_string()),
+    ]);
+    assert_eq!(
+        render("${home} costs ${price}", vars).expect("Should expand HashMap definitions"),
+        "/home/naomi costs ${12}"
+    );
+}
+
+#[test]
+fn only_references_expand() {
+    let vars = json!({
+        "password": "pa$$word",
+        "note": "abc$#def",
+        "path": "C:\\$Recycle.Bin\\ for ${user}",
+        "open": "${user} and ${",
+        "user": "naomi",
+    });
+    assert_eq!(
+        render("${password} ${note} ${path} ${open}", vars).expect("Should keep plain text"),
+        "pa$$word abc$#def C:\\$Recycle.Bin\\ for naomi naomi and ${"
+    );
+}
+
+#[test]
+fn cycle_reports_chain() {
+    let vars = json!({"a": "${b}", "b": "x${c}", "c": "${a}"});
+    let err = render("${a}", vars).expect_err("Cycle should be an error");
+    assert!(
+        err.to_string().contains("a -> b -> c -> a"),
+        "Unexpected error: {err}"
+    );
+
+    let err = render("${me}", json!({"me": "${me}"})).expect_err("Self reference is a cycle");
+    assert!(err.to_string().contains("me -> me"), "Unexpected error: {err}");
+}
+
+#[test]
+fn depth_limit() {
+    let vars = json!({"a": "${b}", "b": "${c}", "c": "end"});
+    let template = Template::from(Cursor::new("${a}"));
+    let generate = |max_expand_depth| GenerateTemplate {
+        template: &template,
+        variables: &VariableMap::from(vars.clone()),
+        options: GenerateOptions { max_expand_depth, ..Default::default() },
+    }.generate();
+    assert_eq!(generate(2).expect("Depth 2 is enough"), "end");
+    assert!(generate(1).is_err());
+    assert_eq!(generate(0).expect("Depth 0 disables expansion"), "${b}");
+}
+
+#[test]
+fn missing_nested_reference() {
+    let vars: Value = json!({"a": "${nope}"});
+    assert!(render("${a}", vars).is_err());
+}
@@ -0,0 +1,63 @@
+use la_template_base::*;
+use serde_json::json;
+use std::io::Cursor;
+
+fn render(template: &str) -> common::MyResult<String> {
+    let vars = json!({"replicas": "3", "port": "8080", "ratio": "0.5", "name": "web", "max": "9223372036854775807"});
+    generate_template(Cursor::new(template), vars)
+}
+
+#[test]
+fn arithmetic_and_comparisons() {
+    assert_eq!(
+        render("${replicas * 2} ${port + 1} ${replicas > 3} ${(replicas + 1) * ratio} ${name + \"-0\"} ${7 % replicas == 1 && !false}")
+            .expect("Should evaluate"),
+        "6 8081 false 2 web-0 true"
+    );
+    assert_eq!(
+        render("\"${replicas > 1 || port / 0 | escape(\"json\")}\"").expect("|| short-circuits"),
+        "\"true\""
+    );
+}
+
+#[test]
+fn type_and_overflow_errors() {
+    let err = render("${name * 2}").expect_err("Strings do not multiply");
+    assert_eq!(err.to_string(), "Cannot apply '*' to string \"web\" and int 2");
+    let err = render("${max + 1}").expect_err("Should overflow");
+    assert_eq!(err.to_string(), "Integer overflow in 9223372036854775807 + 1");
+    let err = render("${port / (replicas - 3)}").expect_err("Should divide by zero");
+    assert_eq!(err.to_string(), "Division by zero in 8080 / 0");
+    let err = parse_template(Cursor::new("${port +}")).expect_err("Incomplete expression");
+    assert!(err.to_string().contains("operand was expected"), "{}", err);
+}
+
+#[test]
+fn partial_keeps_undefined_operands() {
+    let template = parse_template(Cursor::new("${replicas * 2} ${(base - 1) * replicas | escape(\"json\")}"))
+        .expect("Should parse");
+    let generate = GenerateTemplate {
+        template: &template.into(),
+        variables: &json!({"replicas": "3"}).into(),
+        options: Default::default(),
+    };
+    assert_eq!(
+        generate.partial_text().expect("Should render partially"),
+        "6 ${(base - 1) * replicas | escape(\"json\")}"
+    );
+}
+
+#[test]
+fn delimiters_and_dashes() {
+    assert_eq!(render("${name + \"}\"} ${name + \"\\\"}\"}").expect("Quoted '}' is not the close"), "web} web\"}");
+    assert_eq!(render("a ${-replicas} ${ -replicas} ${0 - replicas}").expect("Should evaluate"), "a3 -3 -3");
+    let template = parse_template(Cursor::new("${ -base * 2}")).expect("Should parse");
+    let generate = GenerateTemplate {
+        template: &template.into(),
+        variables: &json!({}).into(),
+        options: Default::default(),
+    };
+    let source = generate.partial_text().expect("Should render partially");
+    assert_eq!(source, "${ -base * 2}");
+    assert_eq!(parse_template(Cursor::new(source)).expect("Should parse").symbols(), &vec!["base".to_string()]);
+}
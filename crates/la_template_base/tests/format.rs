@@ -0,0 +1,50 @@
+use la_template_base::*;
+use serde_json::json;
+use std::io::Cursor;
+
+fn render(template: &str) -> common::MyResult<String> {
+    let vars = json!({"price": 12.5, "id": 42, "name": "disk", "flags": 255, "delta": -7, "ratio": "0.125"});
+    generate_template(Cursor::new(template), vars)
+}
+
+#[test]
+fn width_fill_alignment_precision() {
+    assert_eq!(
+        render("[${price:.2}] [${id:>8}] [${id:08}] [${name:*^8}] [${name:.2}] [${ratio:+.1}] [${delta:05}]")
+            .expect("Should format"),
+        "[12.50] [      42] [00000042] [**disk**] [di] [+0.1] [-0007]"
+    );
+}
+
+#[test]
+fn radix_and_filters() {
+    assert_eq!(
+        render("${flags:x} ${flags:#06X} ${flags:o} ${delta:#b} \"${id * 2:>5 | escape(\"json\")}\"").expect("Should format"),
+        "ff 0x00FF 377 -0b111 \"   84\""
+    );
+    let err = render("${name:x}").expect_err("Strings have no hex form");
+    assert_eq!(err.to_string(), "Format spec x expects an integer, got \"disk\"");
+}
+
+#[test]
+fn specs_round_trip() {
+    let template = parse_template(Cursor::new("${id:0>8.3 | escape(\"json\")}")).expect("Should parse");
+    assert_eq!(template.to_source().expect("Should write back"), "${id | format(\"0>8.3\") | escape(\"json\")}");
+    let err = parse_template(Cursor::new("${id:8q}")).expect_err("Unknown type");
+    assert_eq!(err.to_string(), "1:1: Invalid format spec \"8q\": unexpected \"q\"");
+}
+
+#[test]
+fn close_delimiter_fill() {
+    assert_eq!(render("[${id:}>5}] [${name:}<6}]").expect("Should format"), "[}}}42] [disk}}]");
+    let template = parse_template(Cursor::new("${id:}^6}")).expect("Should parse");
+    assert_eq!(template.to_source().expect("Should write back"), "${id | format(\"}^6\")}");
+}
+
+#[test]
+fn width_and_precision_are_capped() {
+    assert_eq!(render("${id:4096}").expect("The maximum is allowed").len(), MAX_FORMAT_WIDTH);
+    let err = parse_template(Cursor::new("${x:999999999}")).expect_err("Width over the limit");
+    assert_eq!(err.to_string(), "1:1: Format spec \"999999999\" has a number out of range: 999999999, the maximum is 4096");
+    assert!(parse_template(Cursor::new("${x:.5000}")).is_err());
+}
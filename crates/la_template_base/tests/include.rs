@@ -0,0 +1,85 @@
+mod common;
+
+use ::common::MyResult;
+use la_template_base::*;
+use serde_json::json;
+
+fn resolve(files: &[(&str, &str)], path: &str, search: &IncludePath) -> MyResult<ConcreteTemplate> {
+    common::resolve(files, path, |template, path, load| template.resolve_includes(path, search, load))
+}
+
+#[test]
+fn search_path_and_bindings() {
+    let files = [
+        ("site/play.t.yml", "${% include \"@partials/task.t.yml\" with name = \"Restart \" + service %}\
+                             ${% include \"task.t.yml\" with name = \"Check \" + service, retries = 3 %}\
+                             ${% include \"footer.t.yml\" %}"),
+        ("shared/partials/task.t.yml", "- name: ${name}\n  retries: ${retries}\n"),
+        ("site/task.t.yml", "- name: \"${name | escape(\"yaml\")}\"\n  retries: ${retries}\n"),
+        ("shared/footer.t.yml", "# ${service} by ${la.user}\n"),
+    ];
+    let search = IncludePath::new()
+        .with_dir("shared")
+        .with_aliases([("partials", "shared/partials")]);
+    let template = resolve(&files, "site/play.t.yml", &search).expect("Should resolve");
+    let vars = json!({ "service": "nginx", "retries": 1 });
+    let options = GenerateOptions { builtins: Builtins::default().with_user("ops"), ..Default::default() };
+    assert_eq!(
+        GenerateTemplate { template: &template.into(), variables: &vars.into(), options }.generate().expect("Should render"),
+        "- name: Restart nginx\n  retries: 1\n- name: \"Check nginx\"\n  retries: 3\n# nginx by ops\n"
+    );
+}
+
+#[test]
+fn cycles_and_misses() {
+    let files = [
+        ("a.t", "${% include \"b.t\" %}"),
+        ("b.t", "${% include \"a.t\" %}"),
+        ("c.t", "${% include \"missing.t\" %}"),
+    ];
+    let err = |path: &str| resolve(&files, path, &IncludePath::new().with_dir("lib"))
+        .expect_err("Should fail")
+        .to_string();
+    assert_eq!(err("a.t"), "Include cycle: a.t -> b.t -> a.t");
+    assert_eq!(err("c.t"), "Cannot include \"missing.t\" from c.t, tried:\nmissing.t: No such file\nlib/missing.t: No such file");
+
+    let files = [("a.t", "${% include \"./a.t\" %}")];
+    let err = resolve(&files, "a.t", &IncludePath::new()).expect_err("./a.t is a.t");
+    assert_eq!(err.to_string(), "Include cycle: a.t -> ./a.t");
+
+    let files = [("lib/a.t", "${% include \"../lib/a.t\" %}")];
+    let err = resolve(&files, "lib/a.t", &IncludePath::new()).expect_err("../lib/a.t is lib/a.t without the disk");
+    assert_eq!(err.to_string(), "Include cycle: lib/a.t -> lib/../lib/a.t");
+}
+
+#[test]
+fn same_name_in_search_dir() {
+    let files = [
+        ("site/header.t", "[${% include \"header.t\" %}]"),
+        ("lib/header.t", "${title}"),
+    ];
+    let template = resolve(&files, "site/header.t", &IncludePath::new().with_dir("lib"))
+        .expect("The including template is skipped");
+    assert_eq!(template.to_source().expect("Should write back"), "[${title}]");
+}
+
+#[test]
+fn bindings_reach_macro_calls() {
+    let files = [
+        ("page.t", "${% macro greet(who) %}hello ${who}${% endmacro %}\
+                    ${% include \"part.t\" with name = \"x\" + suffix %}"),
+        ("part.t", "${% call greet(name) %}, ${% call greet(\"to \" + name) %}"),
+    ];
+    let resolve = |files: &[(&str, &str)]| common::resolve(files, "page.t", |template, path, load| {
+        template
+            .resolve_includes(path, &IncludePath::new(), load)?
+            .resolve_macros(path, &IncludePath::new(), load)
+    });
+    let template = resolve(&files).expect("Should resolve");
+    assert_eq!(template.to_source().expect("Should write back"), "hello ${\"x\" + suffix}, hello ${\"to \" + (\"x\" + suffix)}");
+
+    // errors of calls are located in the included template
+    let files = [("page.t", "${% include \"part.t\" with name = 1 %}"), ("part.t", "\n ${% call greet(name) %}")];
+    let err = resolve(&files).expect_err("greet is not defined");
+    assert_eq!(err.to_string(), "part.t:2:2: Unknown macro greet");
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use la_template_base::*;
+use serde_json::json;
+use std::io::Cursor;
+
+#[test]
+fn multi_line_values() {
+    let vars = json!({ "script": "set -e\nmake\n\nmake install\n", "name": "web" });
+    assert_eq!(
+        generate_template(Cursor::new("run: |\n    ${script | indent}done: ${name | indent}"), vars.clone()).expect("Should render"),
+        "run: |\n    set -e\n    make\n\n    make install\ndone: web"
+    );
+    // the front matter indents every substitution, and tabs are kept
+    let template = "+++\nindent = true\n+++\n\t- ${script}";
+    assert_eq!(
+        generate_template(Cursor::new(template), vars.clone()).expect("Should render"),
+        "\t- set -e\n\t  make\n\n\t  make install\n"
+    );
+    let parsed = parse_template(Cursor::new(template)).expect("Should parse");
+    assert_eq!(parsed.to_source().expect("Should write back"), "+++\nindent = true\n+++\n\t- ${script | indent}");
+    assert_eq!(
+        generate_template(Cursor::new("> ${script | indent(\"> \")}"), vars).expect("Should render"),
+        "> set -e\n> make\n\n> make install\n"
+    );
+}
+
+#[test]
+fn includes() {
+    let files = [
+        ("play.t.yml", "tasks:\n  ${% include \"task.t.yml\" indent %}\nhandlers: ${% include \"task.t.yml\" indent %}"),
+        ("task.t.yml", "- name: ${name}\n  shell: |\n    ${script | indent}\n"),
+    ];
+    let template = common::resolve(&files, "play.t.yml", |template, path, load| {
+        template.resolve_includes(path, &IncludePath::new(), load)
+    }).expect("Should resolve");
+    let vars = json!({ "name": "build", "script": "make\nmake install" });
+    assert_eq!(
+        generate_template(template, vars).expect("Should render"),
+        "tasks:\n  - name: build\n    shell: |\n      make\n      make install\n\
+         handlers: - name: build\n            shell: |\n              make\n              make install\n"
+    );
+}
+
+#[test]
+fn output_column() {
+    // the column counts what earlier substitutions wrote on the line
+    let template = "${key}: ${script | indent}";
+    let vars = json!({ "key": "build", "script": "make\nmake install" });
+    assert_eq!(
+        generate_template(Cursor::new(template), vars.clone()).expect("Should render"),
+        "build: make\n       make install"
+    );
+    let partial = GenerateTemplate {
+        template: &Cursor::new(template).into(),
+        variables: &json!({ "key": "build" }).into(),
+        options: Default::default(),
+    }.partial_text().expect("Should render");
+    assert_eq!(partial, "build: ${script | indent}");
+    let parsed = Cursor::new(template).into();
+    let batch = BatchGenerate::new(&parsed, Default::default()).expect("Should plan");
+    assert_eq!(batch.generate(&vars.into()).expect("Should render"), "build: make\n       make install");
+}
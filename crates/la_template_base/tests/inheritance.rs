@@ -0,0 +1,67 @@
+mod common;
+
+use ::common::MyResult;
+use la_template_base::*;
+use serde_json::json;
+
+const BASE: &str = "\
++++
+escape = \"yaml\"
++++
+- hosts: \"${hosts}\"
+  tasks:
+${% block tasks %}
+    - name: \"Install ${package}\"
+${% endblock %}
+${% block handlers %}
+    - name: \"Reload ${service}\"
+${% endblock %}
+";
+
+fn resolve(files: &[(&str, &str)], path: &str) -> MyResult<ConcreteTemplate> {
+    common::resolve(files, path, |template, path, load| template.resolve_inheritance(path, load))
+}
+
+#[test]
+fn overrides_and_super() {
+    let web = "\
+${% extends \"base.t.yml\" %}
+${% block tasks %}
+${% super %}
+    - name: Open port ${port}
+${% endblock tasks %}
+";
+    let canary = "${% extends \"web.t.yml\" %}\n${% block handlers %}${% endblock %}\n";
+    let files = [
+        ("playbooks/base.t.yml", BASE),
+        ("playbooks/web.t.yml", web),
+        ("playbooks/canary.t.yml", canary),
+    ];
+    let template = resolve(&files, "playbooks/canary.t.yml").expect("Should resolve");
+    assert_eq!(template.symbols(), &vec!["hosts".to_string(), "package".to_string(), "port".to_string()]);
+    let vars = json!({ "hosts": "web \"eu\"", "package": "nginx", "port": 443 });
+    // the parent's front matter still applies
+    assert_eq!(
+        generate_template(template, vars).expect("Should render"),
+        "- hosts: \"web \\\"eu\\\"\"\n  tasks:\n    - name: \"Install nginx\"\n    - name: Open port 443\n"
+    );
+
+    // a template without a parent renders its blocks in place
+    let base = resolve(&files, "playbooks/base.t.yml").expect("Should resolve");
+    assert!(!base.tokens().iter().any(|tok| matches!(tok, Token::Tag(_))));
+}
+
+#[test]
+fn mistakes() {
+    let err = |child: &str| resolve(&[("base.t.yml", BASE), ("child.t.yml", child)], "child.t.yml")
+        .expect_err("Should fail")
+        .to_string();
+    assert!(err("${% extends \"base.t.yml\" %}${% block task %}x${% endblock %}").contains("Block task of child.t.yml"));
+    assert!(err("${% extends \"base.t.yml\" %}stray ${% block tasks %}${% endblock %}").contains("inside blocks"));
+    assert!(err("${% extends \"base.t.yml\" %}${% block tasks %}").contains("missing its endblock"));
+    assert!(err("${% extends \"child.t.yml\" %}").contains("extends itself"));
+    assert!(err("${% extends \"./child.t.yml\" %}").contains("extends itself"));
+    assert!(err("${% extends \"sub/../child.t.yml\" %}").contains("extends itself"));
+    assert!(err("${% extends \"missing.t.yml\" %}").contains("Failed to load missing.t.yml, extended by child.t.yml"));
+    assert!(err("${% block tasks %}${% super %}${% endblock %}").contains("no parent template defines"));
+}
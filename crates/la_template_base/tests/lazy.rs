@@ -0,0 +1,65 @@
+use la_template_base::*;
+use std::io::Cursor;
+use std::sync::{atomic::{AtomicU32, Ordering}, Arc};
+
+fn counted(calls: &Arc<AtomicU32>, value: &'static str) -> impl Fn() -> common::MyResult<String> + Send + Sync {
+    let calls = calls.clone();
+    move || {
+        calls.fetch_add(1, Ordering::Relaxed);
+        Ok(value.to_string())
+    }
+}
+
+#[test]
+fn variables_are_send_and_sync() {
+    fn _assert<T: Send + Sync>() {}
+    _assert::<LazyVariables>();
+    _assert::<VariableMap>();
+}
+
+#[test]
+fn computed_once_and_only_when_referenced() {
+    let (secret_calls, checksum_calls) = (Arc::new(AtomicU32::new(0)), Arc::new(AtomicU32::new(0)));
+    let vars = LazyVariables::new()
+        .define("secret", counted(&secret_calls, "hunter2"))
+        .define("checksum", counted(&checksum_calls, "abc123"));
+    assert_eq!(
+        generate_template(Cursor::new("${secret} and ${secret} again"), vars).expect("Should render"),
+        "hunter2 and hunter2 again"
+    );
+    assert_eq!(secret_calls.load(Ordering::Relaxed), 1);
+    assert_eq!(checksum_calls.load(Ordering::Relaxed), 0);
+}
+
+#[test]
+fn failures_are_reported_not_missing() {
+    let vars = LazyVariables::new().define("token", || Err("vault unreachable".into()));
+    let err = generate_template(Cursor::new("${token}"), vars).expect_err("token cannot be computed");
+    assert_eq!(err.to_string(), "Failed to compute token: vault unreachable");
+}
+
+#[test]
+fn cache_can_be_cleared() {
+    let calls = Arc::new(AtomicU32::new(0));
+    let mut vars = LazyVariables::new().define("id", counted(&calls, "42"));
+    assert_eq!(vars.get_defn("id").expect("id is defined"), "42");
+    assert!(vars.is_cached("id"));
+    vars.clear_cache();
+    assert!(!vars.is_cached("id"));
+    vars.get_defn("id").expect("id is defined");
+    assert_eq!(calls.load(Ordering::Relaxed), 2);
+}
+
+#[test]
+fn computed_again_for_each_render() {
+    let calls = Arc::new(AtomicU32::new(0));
+    let vars: VariableMap = LazyVariables::new().define("id", counted(&calls, "42")).into();
+    let template: Template = Cursor::new("${id}-${id}").into();
+    let generate = GenerateTemplate { template: &template, variables: &vars, options: Default::default() };
+    assert_eq!(generate.generate().expect("Should render"), "42-42");
+    assert_eq!(generate.generate().expect("Should render"), "42-42");
+    assert_eq!(calls.load(Ordering::Relaxed), 2, "Once per render");
+    let batch = BatchGenerate::new(&template, Default::default()).expect("Should plan");
+    batch.generate(&vars).expect("Should render");
+    assert_eq!(calls.load(Ordering::Relaxed), 3);
+}
@@ -0,0 +1,71 @@
+mod common;
+
+use std::path::Path;
+
+use ::common::MyResult;
+use la_template_base::*;
+use serde_json::json;
+
+fn resolve(files: &[(&str, &str)], path: &str) -> MyResult<ConcreteTemplate> {
+    common::resolve(files, path, |template, path, load| {
+        template.resolve_macros(path, &IncludePath::new().with_dir("lib"), load)
+    })
+}
+
+#[test]
+fn calls_and_imports() {
+    let files = [
+        ("play.t.yml", "${% import \"exec.t.yml\" %}\n\
+                        ${% macro exec_each(name, command) %}\n\
+                        ${% call exec(name, command + \" | tee log\") %}\n\
+                        ${% endmacro %}\n\
+                        - block:\n  \
+                          ${% call exec(\"Back up keys\", \"cp keys keys.bck\") indent %}\n  \
+                          ${% call exec_each(\"Dedupe \" + file, \"sort -u \" + file) indent %}\n"),
+        ("lib/exec.t.yml", "Ignored text\n\
+                            ${% macro exec(name, command) %}\n\
+                            - name: ${name}\n  \
+                              ${cmd}: multipass exec ${vm} -- ${command}\n\
+                            ${% endmacro %}\n"),
+    ];
+    let template = resolve(&files, "play.t.yml").expect("Should resolve");
+    let vars = json!({ "cmd": "command", "vm": "web", "file": "keys" });
+    assert_eq!(
+        generate_template(template, vars).expect("Should render"),
+        "- block:\n  \
+           - name: Back up keys\n    command: multipass exec web -- cp keys keys.bck\n  \
+           - name: Dedupe keys\n    command: multipass exec web -- sort -u keys | tee log\n"
+    );
+}
+
+#[test]
+fn call_errors() {
+    let err = |files: &[(&str, &str)]| resolve(files, "a.t").expect_err("Should fail").to_string();
+    assert_eq!(err(&[("a.t", "${% call missing() %}")]), "a.t:1:1: Unknown macro missing");
+    assert_eq!(
+        err(&[("a.t", "${% macro m(x) %}${x}${% endmacro %}${% call m(1, 2) %}")]),
+        "a.t:1:37: Macro m takes 1 arguments, got 2"
+    );
+    assert_eq!(
+        err(&[("a.t", "${% import \"b.t\" %}${% call outer() %}"), ("b.t", "${% macro outer() %}${% call inner(1) %}${% endmacro %}")]),
+        "b.t:1:21: Unknown macro inner\n  in macro outer, defined in b.t"
+    );
+    assert_eq!(
+        err(&[("a.t", "${% macro m() %}${% call m() %}${% endmacro %}${% call m() %}")]),
+        "a.t:1:17: Macro cycle: m -> m\n  in macro m, defined in a.t"
+    );
+    assert_eq!(err(&[("a.t", "${% macro m() %}")]), "Missing endmacro for macro m");
+
+    // the error of a call is a diagnostic at the call
+    let err = resolve(&[("a.t", "${% macro m() %}${% endmacro %}\n  ok ${% call m( %}")], "a.t").expect_err("Should fail");
+    let diagnostic = err.downcast_ref::<Diagnostic>().expect("Should locate the call");
+    assert_eq!((diagnostic.path.as_deref(), diagnostic.line, diagnostic.column), (Some(Path::new("a.t")), 2, 6));
+    assert_eq!(diagnostic.message, "Missing ')' after the arguments of call m");
+}
+
+#[test]
+fn parenthesized_arguments() {
+    let files = [("a.t", "${% macro f(x, label) %}${label}=${x}${% endmacro %}${% call f((n + 1) * 2, \"a)b\") %}")];
+    let template = resolve(&files, "a.t").expect("Should resolve");
+    assert_eq!(generate_template(template, json!({ "n": 3 })).expect("Should render"), "a)b=8");
+}
@@ -0,0 +1,41 @@
+use la_template_base::*;
+use serde_json::{json, Value};
+use std::io::Cursor;
+
+fn partial(template: &str, vars: Value) -> common::MyResult<ConcreteTemplate> {
+    GenerateTemplate {
+        template: &BufReadTemplate::new(Cursor::new(template))?.into(),
+        variables: &vars.into(),
+        options: Default::default(),
+    }.partial()
+}
+
+#[test]
+fn stages() {
+    let template = "org: ${org}, env: ${env | escape(\"url\")}, cost: \\$${cost}\n";
+    let stage = partial(template, json!({ "org": "acme $corp" })).expect("Should render known vars");
+    assert_eq!(stage.symbols(), &vec!["env".to_string(), "cost".to_string()]);
+    let text = stage.to_source().expect("Should write back");
+    assert_eq!(text, "org: acme \\$corp, env: ${env | escape(\"url\")}, cost: \\$${cost}\n");
+
+    // the leftover text is itself a template
+    assert_eq!(
+        generate_template(Cursor::new(text), json!({ "env": "a b", "cost": "12" })).expect("Should render"),
+        "org: acme $corp, env: a%20b, cost: $12\n"
+    );
+    // strict rendering still refuses missing definitions
+    assert!(generate_template(Cursor::new(template), json!({ "org": "acme" })).is_err());
+}
+
+#[test]
+fn front_matter_survives() {
+    let template = "+++\nescape = \"json\"\n+++\n[\"${a}\", \"${b}\"]";
+    let text = partial(template, json!({ "a": "x\"" }))
+        .and_then(|t| t.to_source())
+        .expect("Should render");
+    assert_eq!(text, "+++\nescape = \"json\"\n+++\n[\"x\\\"\", \"${b}\"]");
+    assert_eq!(
+        generate_template(Cursor::new(text), json!({ "b": "y" })).expect("Should render"),
+        "[\"x\\\"\", \"y\"]"
+    );
+}
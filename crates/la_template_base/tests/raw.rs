@@ -0,0 +1,34 @@
+use la_template_base::*;
+use serde_json::json;
+use std::io::Cursor;
+
+#[test]
+fn verbatim_content() {
+    let template = "run:\n  ${% raw %}\n  echo ${HOME} \\${x} ${% if %} $${\n  ${% endraw %}\nname: ${name}";
+    let parsed = parse_template(Cursor::new(template)).expect("Should parse");
+    assert_eq!(
+        parsed.tokens()[0],
+        Token::from("run:\n  echo ${HOME} \\${x} ${% if %} $${\nname: ")
+    );
+    assert_eq!(
+        generate_template(Cursor::new(template), json!({ "name": "web" })).expect("Should render"),
+        "run:\n  echo ${HOME} \\${x} ${% if %} $${\nname: web"
+    );
+    // written back, the content reads as literal text again
+    let source = parsed.to_source().expect("Should write back");
+    assert_eq!(
+        generate_template(Cursor::new(source), json!({ "name": "web" })).expect("Should render"),
+        "run:\n  echo ${HOME} \\${x} ${% if %} $${\nname: web"
+    );
+    assert_eq!(
+        generate_template(Cursor::new("a ${% raw -%}  ${x}  ${%- endraw %} b"), json!({})).expect("Should render"),
+        "a ${x} b"
+    );
+}
+
+#[test]
+fn unbalanced() {
+    let err = |template: &str| parse_template(Cursor::new(template)).expect_err("Should fail").to_string();
+    assert_eq!(err("${% raw %} ${x}"), "1:1: Unterminated raw block: missing ${% endraw %}");
+    assert_eq!(err("${x} ${% endraw %}"), "1:6: endraw without a raw block");
+}
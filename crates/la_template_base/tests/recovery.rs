@@ -0,0 +1,46 @@
+use la_template_base::*;
+use serde_json::json;
+use std::io::Cursor;
+
+fn recover(src: &[u8]) -> (ConcreteTemplate, Vec<String>) {
+    let (template, diagnostics) = parse_template_recovering(Cursor::new(src), Default::default()).expect("Should recover");
+    (template, diagnostics.iter().map(ToString::to_string).collect())
+}
+
+#[test]
+fn every_error_at_once() {
+    let (template, diagnostics) = recover(
+        b"name: ${name}\n\
+         port: ${port +}\n\
+         host: ${host | bogus}\n\
+         cost: $5 \xff and ${% %}\n\
+         user: ${user\n\
+         ok: ${ok}\n",
+    );
+    assert_eq!(diagnostics, [
+        "2:7: Expression \"port +\" ends where an operand was expected",
+        "3:7: Unknown filter \"bogus\"",
+        "4:7: Expected '{' or '#' after '$'",
+        "4:10: Invalid UTF-8 at byte offset 61",
+        "4:16: Invalid block tag name \"\"",
+        "5:7: Unterminated '${': missing '}'",
+    ]);
+    // the unterminated `${` runs into the next construct, which still counts
+    let (_, unterminated) = recover(b"a ${user\nb ${ok}");
+    assert_eq!(unterminated, ["1:3: Unterminated '${': missing '}'"]);
+    // what parsed renders
+    assert_eq!(
+        generate_template(template, json!({ "name": "web", "ok": "yes" })).expect("Should render"),
+        "name: web\nport: \nhost: \ncost: 5  and \nuser: yes\n"
+    );
+}
+
+#[test]
+fn strict_parse_reports_all() {
+    let err = parse_template(Cursor::new("+++\nescape = \"nope\"\n+++\n${a ${b}\n${c:8q}"))
+        .expect_err("Should fail")
+        .to_string();
+    assert_eq!(err.lines().count(), 3, "{}", err);
+    assert!(err.lines().next().unwrap().starts_with("2:1: "), "{}", err);
+    assert!(err.contains("\n4:1: Unterminated '${': missing '}'\n5:1: Invalid format spec"), "{}", err);
+}
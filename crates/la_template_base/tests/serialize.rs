@@ -0,0 +1,91 @@
+use la_template_base::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Cursor;
+
+#[derive(Serialize)]
+struct Db {
+    host: String,
+    port: u16,
+}
+
+#[derive(Serialize)]
+enum Tier {
+    Production,
+}
+
+#[derive(Serialize)]
+struct Config {
+    name: &'static str,
+    db: Db,
+    replicas: Vec<&'static str>,
+    tier: Tier,
+    debug: bool,
+    owner: Option<String>,
+    labels: BTreeMap<u8, &'static str>,
+}
+
+fn config() -> Config {
+    Config {
+        name: "api",
+        db: Db { host: "db.internal".to_string(), port: 5432 },
+        replicas: vec!["a", "b"],
+        tier: Tier::Production,
+        debug: false,
+        owner: None,
+        labels: BTreeMap::from([(1, "blue")]),
+    }
+}
+
+#[test]
+fn nested_fields_are_dotted() {
+    let template = "${name} ${db.host}:${db.port} ${replicas.1} ${tier} ${debug} ${labels.1}";
+    let vars = VariableMap::from_serialize(&config()).expect("Config should flatten");
+    assert_eq!(
+        generate_template(Cursor::new(template), vars).expect("Should render"),
+        "api db.internal:5432 b Production false blue"
+    );
+}
+
+#[test]
+fn none_is_undefined() {
+    let vars = flatten_vars(&config()).expect("Config should flatten");
+    assert!(!vars.contains_key("owner"));
+    let err = generate_template(Cursor::new("${owner}"), vars).expect_err("owner is None");
+    assert!(err.to_string().contains("owner"), "{}", err);
+}
+
+#[test]
+fn top_level_must_be_a_struct() {
+    let err = flatten_vars(&"api").expect_err("A str has no fields");
+    assert!(err.to_string().contains("struct or map"), "{}", err);
+    let err = flatten_vars(&vec!["a", "b"]).expect_err("A Vec has no fields");
+    assert_eq!(err.to_string(), "Variables must be a struct or map, got a sequence");
+    let err = flatten_vars(&("a", 1)).expect_err("A tuple has no fields");
+    assert_eq!(err.to_string(), "Variables must be a struct or map, got a tuple");
+}
+
+#[test]
+fn empty_collections_define_nothing() {
+    #[derive(Serialize)]
+    struct Empty {
+        tags: Vec<String>,
+        labels: BTreeMap<String, String>,
+    }
+    let vars = flatten_vars(&Empty { tags: Vec::new(), labels: BTreeMap::new() }).expect("Should flatten");
+    assert!(vars.is_empty(), "{:?}", vars);
+    assert!(flatten_vars(&BTreeMap::<String, String>::new()).expect("Should flatten").is_empty());
+}
+
+#[test]
+fn structured_filters_need_json() {
+    // only leaves are flattened, so `db` itself is not a variable
+    let vars = VariableMap::from_serialize(&config()).expect("Config should flatten");
+    let err = generate_template(Cursor::new("${db | to_yaml}"), vars).expect_err("db is not a leaf");
+    assert!(err.to_string().contains("db"), "{}", err);
+    let vars = serde_json::to_value(config()).expect("Config should serialize");
+    assert_eq!(
+        generate_template(Cursor::new("${db | to_yaml}"), vars).expect("Should render"),
+        "host: db.internal\nport: 5432"
+    );
+}
@@ -0,0 +1,61 @@
+use la_template_base::*;
+use serde_json::{json, Value};
+use std::io::Cursor;
+
+fn strict(template: &str, vars: Value) -> common::MyResult<String> {
+    GenerateTemplate {
+        template: &BufReadTemplate::new(Cursor::new(template))?.into(),
+        variables: &vars.into(),
+        options: GenerateOptions { strict: true, ..Default::default() },
+    }.generate()
+}
+
+#[test]
+fn unused_vars_fail() {
+    let template = "hello ${world_name}, this is ${name}";
+    assert_eq!(
+        strict(template, json!({ "world_name": "world", "name": "me" })).expect("Everything is used"),
+        "hello world, this is me"
+    );
+    let err = strict(template, json!({ "world_name": "w", "name": "me", "over": "x" }))
+        .expect_err("Over-defining fails in strict mode");
+    assert_eq!(err.to_string(), "Unused variables: over");
+}
+
+#[test]
+fn did_you_mean() {
+    let err = strict("hello ${world_name}, this is ${name}", json!({ "world_nmae": "w", "name": "me", "nam": "x" }))
+        .expect_err("Typo");
+    assert_eq!(
+        err.to_string(),
+        "Missing definition: [\"world_name\"]\nUnused variables: nam (did you mean name?), world_nmae (did you mean world_name?)"
+    );
+}
+
+#[test]
+fn expanded_references_count_as_used() {
+    let vars = json!({ "url": "https://${host}/", "host": "example.org" });
+    let expand = GenerateOptions { strict: true, max_expand_depth: DEFAULT_EXPAND_DEPTH, ..Default::default() };
+    assert_eq!(GenerateTemplate {
+        template: &BufReadTemplate::new(Cursor::new("${url}")).expect("Should parse").into(),
+        variables: &vars.clone().into(),
+        options: expand,
+    }.generate().expect("host is used by url"), "https://example.org/");
+    assert!(strict("${url}", vars).is_err());
+}
+
+/// A map that can look names up but not list them
+struct Env;
+
+impl VariableTrait for Env {
+    fn _get_defn<'a>(&'a self, key: &str) -> common::MyResult<std::borrow::Cow<'a, str>> {
+        Ok(key.to_uppercase().into())
+    }
+}
+
+#[test]
+fn names_default_to_unknown() {
+    assert_eq!(Env.names(), None);
+    assert_eq!(ExpandVariables::new(&Env, 8).names(), None);
+    assert_eq!(json!({ "a": "1" }).names(), Some(vec!["a"]));
+}
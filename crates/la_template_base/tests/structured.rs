@@ -0,0 +1,51 @@
+use la_template_base::*;
+use serde_json::json;
+use std::io::Cursor;
+
+fn render(template: &str, escape: Option<EscapeMode>) -> String {
+    let vars = json!({
+        "hosts": ["web-1", "web 2", "true"],
+        "db": { "host": "db.local", "ports": [5432, 5433], "tls": { "verify": false }, "tags": [] },
+        "name": "api",
+        "version": "1.10",
+        "port": 8080,
+    });
+    GenerateTemplate {
+        template: &BufReadTemplate::new(Cursor::new(template)).expect("Should parse").into(),
+        variables: &vars.into(),
+        options: GenerateOptions { escape, ..Default::default() },
+    }.generate().expect("Should render")
+}
+
+#[test]
+fn yaml_blocks() {
+    let yaml = Some(EscapeMode::Yaml);
+    assert_eq!(
+        render("all:\n  hosts:\n    ${hosts | to_yaml}\n  name: ${name}\n", yaml),
+        "all:\n  hosts:\n    - web-1\n    - web 2\n    - \"true\"\n  name: \"api\"\n"
+    );
+    // after a key, the block starts on the next line
+    assert_eq!(
+        render("  db: ${db | to_yaml}\n  name: ${name | to_yaml}\n", yaml),
+        "  db: \n    host: db.local\n    ports:\n      - 5432\n      - 5433\n    tags: []\n    tls:\n      verify: false\n  name: api\n"
+    );
+}
+
+#[test]
+fn json_values() {
+    assert_eq!(
+        render("{\n  \"hosts\": ${hosts | to_json},\n  \"name\": \"${name}\"\n}", Some(EscapeMode::Json)),
+        "{\n  \"hosts\": [\n    \"web-1\",\n    \"web 2\",\n    \"true\"\n  ],\n  \"name\": \"api\"\n}"
+    );
+    // without a filter, collections read as compact JSON
+    assert_eq!(render("${hosts}", None), "[\"web-1\",\"web 2\",\"true\"]");
+}
+
+#[test]
+fn scalars_keep_their_type() {
+    assert_eq!(
+        render("version: ${version | to_yaml}\nport: ${port | to_yaml}\nnext: ${port + 1 | to_yaml}", None),
+        "version: \"1.10\"\nport: 8080\nnext: 8081"
+    );
+    assert_eq!(render("[${version | to_json}, ${port | to_json}]", None), "[\"1.10\", 8080]");
+}
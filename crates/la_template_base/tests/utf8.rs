@@ -0,0 +1,80 @@
+use la_template_base::*;
+use serde_json::json;
+use std::io::{BufReader, Cursor};
+
+#[test]
+fn multi_byte_text() {
+    // a one-byte buffer splits every multi-byte char across reads
+    let template = BufReader::with_capacity(1, Cursor::new("héllo ${wörld} — ✓ \\$"));
+    assert_eq!(
+        generate_template(template, json!({ "wörld": "мир" })).expect("Should render"),
+        "héllo мир — ✓ $"
+    );
+}
+
+#[test]
+fn multi_byte_delimiters() {
+    let options = ParseOptions { sigil: '§', open: '«', close: '»', escape: '¬', ..Default::default() };
+    let template = BufReadTemplate::with_options(
+        Cursor::new("§«greeting» §«- name | escape(\"json\") -» costs ¬§5 ${kept} §«# note #»\n§«% tag %»"),
+        options,
+    ).expect("Should parse");
+    assert_eq!(template.symbols(), &vec!["greeting".to_string(), "name".to_string()]);
+    assert_eq!(template.tokens()[2], Token::from("costs §5 ${kept} \n"));
+    assert_eq!(
+        template.tokens().last(),
+        Some(&Token::Tag(Tag { name: "tag".to_string(), args: "".to_string(), indent: String::new(), span: None, origin: None }))
+    );
+    let same = ParseOptions { close: '«', ..options };
+    assert!(parse_template_with(Cursor::new(""), same).is_err());
+}
+
+#[test]
+fn invalid_utf8_offset() {
+    let err = parse_template(Cursor::new(b"ok ${a} \xE2\x82 broken".to_vec()))
+        .expect_err("Should reject invalid UTF-8");
+    assert!(
+        err.to_string() == "1:9: Invalid UTF-8 at byte offset 8",
+        "Unexpected error: {err}"
+    );
+}
+
+#[test]
+fn binary_literals() {
+    let template = b"\x89PNG\r\n\x1a\n${name}\xff\xfe \\$ \xe9t\xe9".to_vec();
+    let options = ParseOptions { binary: true, ..Default::default() };
+    let template: Template = BufReadTemplate::with_options(Cursor::new(template), options)
+        .expect("Should parse binary literals")
+        .into();
+    assert_eq!(template.tokens()[0], Token::Bytes(b"\x89PNG\r\n\x1a\n".to_vec()));
+    let generate = GenerateTemplate {
+        template: &template,
+        variables: &json!({ "name": "ünï" }).into(),
+        options: Default::default(),
+    };
+    assert_eq!(
+        generate.generate_bytes().expect("Should render"),
+        b"\x89PNG\r\n\x1a\n\xc3\xbcn\xc3\xaf\xff\xfe $ \xe9t\xe9".to_vec()
+    );
+    assert!(generate.generate().is_err(), "Text output cannot hold binary literals");
+
+    // constructs themselves must still be UTF-8
+    let err = parse_template_with(Cursor::new(b"ok ${na\xffme}".to_vec()), options)
+        .expect_err("Should reject binary variable names");
+    assert!(err.to_string().contains("Invalid UTF-8"), "Unexpected error: {err}");
+}
+
+#[test]
+fn binary_trim_markers() {
+    let template = b"\x89PNG \t\n${- name -}\r\n \xff\xfe\n".to_vec();
+    let options = ParseOptions { binary: true, ..Default::default() };
+    let template: Template = BufReadTemplate::with_options(Cursor::new(template), options)
+        .expect("Should parse binary literals")
+        .into();
+    let generate = GenerateTemplate {
+        template: &template,
+        variables: &json!({ "name": "x" }).into(),
+        options: Default::default(),
+    };
+    assert_eq!(generate.generate_bytes().expect("Should render"), b"\x89PNGx\xff\xfe\n".to_vec());
+}
@@ -0,0 +1,72 @@
+use la_template_base::*;
+use serde_json::json;
+use std::io::Cursor;
+
+fn tokens(template: &str) -> Vec<Token> {
+    parse_template(Cursor::new(template))
+        .expect("Should parse")
+        .tokens()
+        .clone()
+}
+
+fn tag_tok(name: &str, args: &str) -> Token {
+    Token::Tag(Tag { name: name.to_string(), args: args.to_string(), indent: String::new(), span: None, origin: None })
+}
+
+#[test]
+fn trim_markers() {
+    assert_eq!(
+        tokens("a  \n ${- x -} \n\tb"),
+        vec![Token::from("a"), Token::Var(0), Token::from("b")]
+    );
+    assert_eq!(
+        tokens("a ${-x} b ${x-} c"),
+        vec![Token::from("a"), Token::Var(0), Token::from(" b "), Token::Var(0), Token::from("c")]
+    );
+    assert_eq!(
+        tokens("key:\n  ${%- tag arg -%}\n  value"),
+        vec![Token::from("key:"), tag_tok("tag", "arg"), Token::from("value")]
+    );
+    // a trim marker never reaches past the next construct
+    assert_eq!(
+        tokens("${x -}${y}  z"),
+        vec![Token::Var(0), Token::Var(1), Token::from("  z")]
+    );
+}
+
+#[test]
+fn standalone_tags() {
+    assert_eq!(
+        tokens("- a\n  ${% tag %}  \n- b\n"),
+        vec![Token::from("- a\n"), tag_tok("tag", ""), Token::from("- b\n")]
+    );
+    assert_eq!(
+        tokens("${% first %}\n${% second x %}\r\nbody\n${% last %}"),
+        vec![tag_tok("first", ""), tag_tok("second", "x"), Token::from("body\n"), tag_tok("last", "")]
+    );
+}
+
+#[test]
+fn not_standalone() {
+    // substitutions keep their line
+    assert_eq!(
+        tokens("a\n${x}\nb"),
+        vec![Token::from("a\n"), Token::Var(0), Token::from("\nb")]
+    );
+    // text on the same line keeps the tag inline
+    assert_eq!(
+        tokens("a\nkey: ${% tag %}\nb"),
+        vec![Token::from("a\nkey: "), tag_tok("tag", ""), Token::from("\nb")]
+    );
+    assert_eq!(
+        tokens("${x} ${% tag %}\n"),
+        vec![Token::Var(0), Token::from(" "), tag_tok("tag", ""), Token::from("\n")]
+    );
+}
+
+#[test]
+fn bad_tags() {
+    assert!(parse_template(Cursor::new("${% tag }")).is_err());
+    assert!(parse_template(Cursor::new("${%  %}")).is_err());
+    assert!(generate_template(Cursor::new("${% unknown %}"), json!({})).is_err());
+}
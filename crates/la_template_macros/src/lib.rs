@@ -0,0 +1,239 @@
+//! `la_template!`: templates parsed at build time into a struct with one
+//! field per variable, so that a missing or misspelled variable is a
+//! compile error rather than a `Missing definition` at runtime.
+
+use std::path::{Path, PathBuf};
+
+use la_template_base::{
+    escape_after, parse_borrowed, Align, BorrowedToken, EscapeMode, Filter, FormatSpec, Layout, ParseOptions,
+};
+use proc_macro2::{Span, TokenStream};
+use quote::quote;
+use syn::{
+    parse::{Parse, ParseStream},
+    parse_macro_input, Ident, LitStr, Token, Visibility,
+};
+
+/// Parses a template, with the grammar of `la_template_base::parse_template`,
+/// into a struct holding its variables and a `render(&self) -> String`.
+///
+/// The path is relative to the crate's `Cargo.toml`. The struct is named after
+/// the file (`greeting.t.txt` becomes `Greeting`) unless spelled out:
+///
+/// ```
+/// # use la_template_macros::la_template;
+/// la_template!(pub struct Hello = "tests/templates/hello_report.t.txt");
+///
+/// let hello = Hello { world_name: "world".into(), name: "pegasust".into() };
+/// assert!(hello.render().starts_with("hello world, this is pegasust reporting."));
+/// ```
+///
+/// Values are substituted as given, without expanding `${...}` inside them.
+/// Escaping follows the template's filters, then its front matter, then its extension.
+///
+/// ```compile_fail
+/// # use la_template_macros::la_template;
+/// la_template!(struct Hello = "tests/templates/hello_report.t.txt");
+///
+/// let hello = Hello { world_nmae: "world".into(), name: "pegasust".into() };
+/// ```
+#[proc_macro]
+pub fn la_template(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as Input);
+    input.expand()
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// `"path"` or `vis struct Name = "path"`
+struct Input {
+    vis: Visibility,
+    name: Option<Ident>,
+    path: LitStr,
+}
+
+impl Parse for Input {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        if input.peek(LitStr) {
+            return Ok(Self { vis: Visibility::Inherited, name: None, path: input.parse()? });
+        }
+        let vis = input.parse()?;
+        input.parse::<Token![struct]>()?;
+        let name = input.parse()?;
+        input.parse::<Token![=]>()?;
+        Ok(Self { vis, name: Some(name), path: input.parse()? })
+    }
+}
+
+impl Input {
+    fn expand(&self) -> syn::Result<TokenStream> {
+        let error = |msg: String| syn::Error::new(self.path.span(), msg);
+        let path = resolve(&self.path.value());
+        let src = std::fs::read(&path)
+            .map_err(|e| error(format!("Failed to read {}: {}", path.display(), e)))?;
+        let template = parse_borrowed(&src, ParseOptions::default())
+            .map_err(|e| error(format!("Failed to parse {}: {}", path.display(), e)))?;
+
+        let name = match &self.name {
+            Some(name) => name.clone(),
+            None => struct_name(&path).ok_or_else(|| error(format!(
+                "Cannot name a struct after {}, use `la_template!(struct Name = \"...\")`",
+                path.display()
+            )))?,
+        };
+        let fields = template.symbols().iter()
+            .map(|symbol| field_ident(symbol)
+                .ok_or_else(|| error(format!("Variable {:?} is not a valid field name", symbol))))
+            .collect::<syn::Result<Vec<_>>>()?;
+
+        let default_escape = template.front_matter().escape.or_else(|| EscapeMode::from_path(&path));
+        let mut literal_len = 0;
+        let steps = template.tokens().iter()
+            .map(|tok| {
+                let (var, filters) = match tok {
+                    BorrowedToken::Str(s) => {
+                        literal_len += s.len();
+                        return Ok(quote! { out.push_str(#s); });
+                    }
+                    BorrowedToken::Var(var) => (*var, &[][..]),
+                    BorrowedToken::Subst(subst) => (subst.var, subst.filters.as_slice()),
+                    BorrowedToken::Bytes(_) => return Err(error("Binary literals cannot be rendered to a String".to_string())),
+                    BorrowedToken::Tag(tag) => return Err(error(format!("Unsupported block tag {:?}", tag.name))),
+                    // fields are strings, so type errors could only surface at runtime
+                    BorrowedToken::Expr(expression) => return Err(error(format!(
+                        "Expression {:?} is not supported by la_template!, compute it into a field",
+                        expression.expr.to_source(template.symbols()).unwrap_or_default()
+                    ))),
+                };
+                let field = &fields[var as usize];
+                let escape = escape_after(filters, default_escape).map(escape_mode).into_iter();
+                let filters = filters.iter()
+                    .map(|f| filter(f).map_err(error))
+                    .collect::<syn::Result<Vec<_>>>()?;
+                Ok(quote! {
+                    let value = ::std::borrow::Cow::Borrowed(self.#field.as_str());
+                    #(let value = #filters;)*
+                    #(let value = #escape.escape_at(value, out.as_bytes());)*
+                    out.push_str(&value);
+                })
+            })
+            .collect::<syn::Result<Vec<_>>>()?;
+
+        let vis = &self.vis;
+        let path = path.to_string_lossy();
+        Ok(quote! {
+            #[derive(Debug, Clone, Default, PartialEq, Eq)]
+            #vis struct #name {
+                #(pub #fields: ::std::string::String,)*
+            }
+
+            impl #name {
+                pub fn render(&self) -> ::std::string::String {
+                    // rebuilds the crate when the template changes
+                    const _: &[u8] = include_bytes!(#path);
+                    let mut out = ::std::string::String::with_capacity(#literal_len);
+                    #({ #steps })*
+                    out
+                }
+            }
+        })
+    }
+}
+
+/// `path` relative to the manifest of the crate being built
+fn resolve(path: &str) -> PathBuf {
+    let manifest_dir = std::env::var_os("CARGO_MANIFEST_DIR").unwrap_or_default();
+    Path::new(&manifest_dir).join(path)
+}
+
+/// `UpperCamelCase` of the file name up to its first `.`
+fn struct_name(path: &Path) -> Option<Ident> {
+    let stem = path.file_name()?.to_str()?.split('.').next()?;
+    let name: String = stem.split(|c: char| !c.is_alphanumeric())
+        .flat_map(|word| {
+            let mut chars = word.chars();
+            chars.next().map(|first| first.to_uppercase().chain(chars))
+        })
+        .flatten()
+        .collect();
+    syn::parse_str(&name).ok()
+}
+
+/// Field named after the variable, raw if it is a keyword
+fn field_ident(symbol: &str) -> Option<Ident> {
+    syn::parse_str(symbol)
+        .or_else(|_| syn::parse_str(&format!("r#{}", symbol)))
+        .ok()
+}
+
+/// Expression applying `filter` to `value`
+fn filter(filter: &Filter) -> Result<TokenStream, String> {
+    match filter {
+        Filter::Escape(mode) => {
+            let mode = escape_mode(*mode);
+            Ok(quote! { #mode.escape_at(value, out.as_bytes()) })
+        }
+        Filter::Indent(indent) => Ok(quote! {
+            ::la_template_base::Filter::Indent(#indent.to_string())
+                .apply_at(value, out.as_bytes())
+                .expect("Indenting never fails")
+        }),
+        Filter::ToYaml(Layout { indent, after_text }) => Ok(quote! {
+            ::la_template_base::Filter::ToYaml(::la_template_base::Layout { indent: #indent.to_string(), after_text: #after_text })
+                .apply(value)
+                .expect("Writing YAML never fails")
+        }),
+        Filter::ToJson(Layout { indent, after_text }) => Ok(quote! {
+            ::la_template_base::Filter::ToJson(::la_template_base::Layout { indent: #indent.to_string(), after_text: #after_text })
+                .apply(value)
+                .expect("Writing JSON never fails")
+        }),
+        // an integer radix is the only way formatting fails, and fields are strings
+        Filter::Format(spec) if spec.radix.is_some() => Err(format!(
+            "Format spec {:?} is not supported by la_template!, format the field in Rust",
+            spec.to_string()
+        )),
+        Filter::Format(spec) => {
+            let FormatSpec { fill, align, plus, alternate, zero, width, precision, radix: _ } = spec;
+            let align = match align {
+                Some(Align::Left) => quote! { Some(::la_template_base::Align::Left) },
+                Some(Align::Right) => quote! { Some(::la_template_base::Align::Right) },
+                Some(Align::Center) => quote! { Some(::la_template_base::Align::Center) },
+                None => quote! { None },
+            };
+            let width = option(*width);
+            let precision = option(*precision);
+            Ok(quote! {
+                ::std::borrow::Cow::<str>::Owned(::la_template_base::FormatSpec {
+                    fill: #fill,
+                    align: #align,
+                    plus: #plus,
+                    alternate: #alternate,
+                    zero: #zero,
+                    width: #width,
+                    precision: #precision,
+                    radix: None,
+                }.apply(&value).expect("Formatting without a radix cannot fail"))
+            })
+        }
+    }
+}
+
+fn option(value: Option<usize>) -> TokenStream {
+    match value {
+        Some(value) => quote! { Some(#value) },
+        None => quote! { None },
+    }
+}
+
+fn escape_mode(mode: EscapeMode) -> TokenStream {
+    let variant = Ident::new(match mode {
+        EscapeMode::None => "None",
+        EscapeMode::Json => "Json",
+        EscapeMode::Yaml => "Yaml",
+        EscapeMode::Shell => "Shell",
+        EscapeMode::Xml => "Xml",
+        EscapeMode::Url => "Url",
+    }, Span::call_site());
+    quote! { ::la_template_base::EscapeMode::#variant }
+}
@@ -0,0 +1,31 @@
+use la_template_macros::la_template;
+
+la_template!("tests/templates/hello_report.t.txt");
+la_template!("tests/templates/greeting.t.json");
+la_template!(pub struct Kind = "tests/templates/kind.t.txt");
+
+#[test]
+fn render_fields() {
+    let hello = HelloReport { world_name: "world".into(), name: "pegasust".into() };
+    assert_eq!(hello.render(), "hello world, this is pegasust reporting. The total cost is $12.");
+}
+
+#[test]
+fn escape_from_extension_and_filters() {
+    let greeting = Greeting { name: "Ann \"A\"".into(), query: "a b&c".into() };
+    assert_eq!(greeting.render(), "{\"name\": \"Ann \\\"A\\\"\", \"link\": \"https://example.com/?q=a%20b%26c\"}\n");
+}
+
+#[test]
+fn keyword_variables_and_front_matter() {
+    let kind = Kind { r#type: "it's".into() };
+    assert_eq!(kind.render(), "kind='it'\\''s' cost=$12\n");
+}
+
+la_template!("tests/templates/line.t.txt");
+
+#[test]
+fn format_specs() {
+    let line = Line { item: "disk".into(), count: "3".into(), price: "12.5".into() };
+    assert_eq!(line.render(), "disk  |   3|12.50\n");
+}
@@ -1,19 +1,19 @@
 mod replace_regex;
 // mod template_fs;
-mod memfs_tracer;
+// mod memfs_tracer;
 
 use replace_regex::*;
 
 use std::{
     collections::HashMap,
     io::{Write},
-    path::{PathBuf},
+    path::{Path, PathBuf},
 };
 
 
 use itertools::{Itertools};
 use la_template_base::{
-    parse_template, GenerateTemplate,
+    parse_template_with, Builtins, EscapeMode, GenerateOptions, GenerateTemplate, IncludePath, ParseOptions,
 };
 use common::{AnyErr, OptionVecTrait, MyResultTrait};
 use serde::{Deserialize, Serialize};
@@ -38,6 +38,20 @@ pub struct ManagerSchema {
     templates: Vec<PathBuf>,
     replace_regex: Option<ReplaceRegexSchema>,
     skip_if_error: Option<bool>,
+    /// Directories `include` searches after the including template's own
+    #[serde(default)]
+    include_dirs: Vec<PathBuf>,
+    /// Aliases of `include` paths: `{"partials": "templates/partials"}`
+    /// lets templates include `@partials/header.t`
+    #[serde(default)]
+    include_aliases: HashMap<String, String>,
+    /// Escape substitutions for the format the output's extension suggests,
+    /// e.g. JSON for `*.json`. Templates may still pick one in their front matter.
+    #[serde(default)]
+    escape_by_extension: bool,
+    /// Copy literal text that is not UTF-8 (binary assets) through unchanged
+    #[serde(default)]
+    binary: bool,
 }
 
 pub fn generate_with_handler(
@@ -60,14 +74,21 @@ pub fn generate_with_handler(
                 .map(|val| (&v.metadata, val.into()))
         })
         .into_group_map_by(|r_mvar| matches!(r_mvar, Result::Ok(_)));
+    let search = manager.include_dirs
+        .iter()
+        .fold(IncludePath::new(), IncludePath::with_dir)
+        .with_aliases(&manager.include_aliases);
+    let options = ParseOptions { binary: manager.binary, ..Default::default() };
     let mut grouped_templates = manager
         .templates
         .iter()
         .map(|template_path| {
-            fs.bufread(template_path)
-                .and_then(|template_buf| {
-                    parse_template(template_buf).map(|p| (template_path, p.into()))
-                })
+            let mut parse = |path: &Path| parse_template_with(fs.bufread(path)?, options);
+            parse(template_path)?
+                .resolve_inheritance(template_path, &mut parse)?
+                .resolve_includes(template_path, &search, &mut parse)?
+                .resolve_macros(template_path, &search, &mut parse)
+                .map(|p| (template_path, p.into()))
         })
         .into_group_map_by(|r_temp| matches!(r_temp, Result::Ok(_)));
 
@@ -119,11 +140,18 @@ pub fn generate_with_handler(
             GenerateTemplate {
                 template: temp,
                 variables: vars,
+                options: GenerateOptions {
+                    escape: manager.escape_by_extension
+                        .then(|| EscapeMode::from_path(&location))
+                        .flatten(),
+                    builtins: Builtins::default().with_template_path(path).with_target(&location),
+                    ..Default::default()
+                },
             }
-            .generate()
+            .generate_bytes()
             .and_then(
                 |outp| fs.bufwrite(location)?.into_inner().unwrap()
-                    .write_all(&outp.into_bytes()).my_result()
+                    .write_all(&outp).my_result()
             )
         })
         .filter_map(|v| v.err())
@@ -1,8 +1,11 @@
 //! Test module for general cases
-use la_template_rs::{ManagerSchema, generate};
+use la_template_rs::ManagerSchema;
+use std::{fs::File, io::BufReader};
 
 
 #[test]
 fn readme_example() {
-    
-}
\ No newline at end of file
+    let manager_f = File::open("tests/configs/world.json").expect("Manager config should exist");
+    let _manager: ManagerSchema = serde_json::from_reader(BufReader::new(manager_f))
+        .expect("README manager config should conform to ManagerSchema");
+}